@@ -0,0 +1,107 @@
+// Traversal speed of the flattened BVH against the pointer based tree it replaced
+//
+//   cargo bench --bench bvh
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use raytracer::vec3::{Point3, Vec3, Color};
+use raytracer::ray::Ray;
+use raytracer::bbox::AABB;
+use raytracer::hittable::Hittable;
+use raytracer::bvh::{BVH, SplitMethod};
+use raytracer::material::{Material, Lambertian};
+use raytracer::texture::SolidTexture;
+use raytracer::triangle::TriangleMesh;
+
+mod common;
+
+use common::{final_scene_objects, PointerBvh};
+
+const RAY_COUNT: usize = 10_000;
+
+fn gray() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)))))
+}
+
+// A finely tessellated sphere of radius 1 at the origin, split into its triangles
+fn sphere_mesh_triangles(rings: usize, segments: usize) -> Vec<Arc<dyn Hittable>> {
+    let mut positions = Vec::new();
+    for ring in 0..=rings {
+        let theta = PI * ring as f64 / rings as f64;
+        for segment in 0..segments {
+            let phi = 2.0 * PI * segment as f64 / segments as f64;
+            positions.push(Point3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()));
+        }
+    }
+
+    let mut indices = Vec::new();
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let next = (segment + 1) % segments;
+            let a = ring * segments + segment;
+            let b = ring * segments + next;
+            let c = (ring + 1) * segments + segment;
+            let d = (ring + 1) * segments + next;
+            indices.push([a, c, b]);
+            indices.push([b, c, d]);
+        }
+    }
+
+    TriangleMesh::new(positions, Vec::new(), Vec::new(), indices, gray()).triangles()
+}
+
+// Rays from random points on a sphere around the objects, aimed at random points inside
+// their bounds
+fn make_rays(objects: &[Arc<dyn Hittable>]) -> Vec<Ray> {
+    let mut bounding_box = AABB::default();
+    for o in objects.iter() {
+        bounding_box.merge(&o.bounding_box().unwrap());
+    }
+    let center = bounding_box.centroid();
+    let radius = (bounding_box.max() - bounding_box.min()).length();
+
+    let mut rng = StdRng::seed_from_u64(1);
+    (0..RAY_COUNT)
+        .map(|_| {
+            let origin = center + Vec3::random_in(-1.0, 1.0, &mut rng).unit_vector() * radius;
+            let target = bounding_box.min() + (bounding_box.max() - bounding_box.min()) * Vec3::random(&mut rng);
+            Ray::new(&origin, &(target - origin).unit_vector(), 0.0)
+        })
+        .collect()
+}
+
+fn bench_traversal(c: &mut Criterion, name: &str, objects: Vec<Arc<dyn Hittable>>) {
+    let rays = make_rays(&objects);
+    let pointer = PointerBvh::new(objects.clone());
+    let median = BVH::with_split_method(objects.clone(), SplitMethod::Median);
+    let sah = BVH::with_split_method(objects, SplitMethod::Sah);
+    let variants: [(&str, &dyn Hittable); 3] = [
+        ("pointer random axis", &pointer),
+        ("flat median", &median),
+        ("flat sah", &sah),
+    ];
+
+    let mut group = c.benchmark_group(name);
+    for (label, bvh) in variants {
+        let mut rng = StdRng::seed_from_u64(2);
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                rays.iter()
+                    .filter(|ray| bvh.hit(ray, 0.0001, f64::MAX, &mut rng).is_some())
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn traversal(c: &mut Criterion) {
+    bench_traversal(c, "final_scene", final_scene_objects(gray()));
+    bench_traversal(c, "sphere_mesh", sphere_mesh_triangles(128, 256));
+}
+
+criterion_group!(benches, traversal);
+criterion_main!(benches);
@@ -0,0 +1,135 @@
+// Fixtures shared by the BVH benchmark and the bvh_report example, which includes this file
+// with #[path], so that they stay out of the library. Each of them uses only part of it.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use raytracer::vec3::Point3;
+use raytracer::ray::Ray;
+use raytracer::bbox::AABB;
+use raytracer::hittable::{Hittable, HitRecord};
+use raytracer::material::Material;
+use raytracer::sphere::Sphere;
+use raytracer::aabox::AABox;
+
+// The uneven layout of final_scene, a grid of boxes of random heights and a dense cloud of
+// small spheres above it, flattened into one list
+pub fn final_scene_objects(material: Arc<dyn Material>) -> Vec<Arc<dyn Hittable>> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut objects = Vec::<Arc<dyn Hittable>>::new();
+
+    const BOXES_PER_SIDE: usize = 20;
+    for i in 0..BOXES_PER_SIDE {
+        for j in 0..BOXES_PER_SIDE {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let z0 = -1000.0 + j as f64 * w;
+            let y1 = rng.gen_range(1.0..101.0);
+            let min = Point3::new(x0, 0.0, z0);
+            let max = Point3::new(x0 + w, y1, z0 + w);
+            objects.push(Arc::new(AABox::new(&min, &max, material.clone())));
+        }
+    }
+
+    let offset = Point3::new(-100.0, 270.0, 395.0);
+    for _ in 0..1000 {
+        let center = Point3::random_in(0.0, 165.0, &mut rng) + offset;
+        objects.push(Arc::new(Sphere::new(&center, 10.0, material.clone())));
+    }
+    objects
+}
+
+// The tree this crate used before flattening, copied from the original build_bvh: every node
+// owns its two children through reference counted pointers, sorts along a randomly chosen axis
+// and splits at the median, is traversed recursively and visits the left child first
+pub struct PointerBvh {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bounding_box: AABB,
+    // Nodes in this subtree, and the number of them on its longest path. Primitives hang off the
+    // nodes directly, there are no leaf nodes
+    nodes: usize,
+    depth: usize,
+}
+
+impl PointerBvh {
+    pub fn new(mut objects: Vec<Arc<dyn Hittable>>) -> Self {
+        let mut rng = StdRng::seed_from_u64(3);
+        PointerBvh::build_bvh(&mut rng, &mut objects[..])
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn build_bvh<R: Rng>(rng: &mut R, objects: &mut [Arc<dyn Hittable>]) -> Self {
+        let comparator = |axis| {
+            move |a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| {
+                a.bounding_box().unwrap().min()[axis].partial_cmp(&b.bounding_box().unwrap().min()[axis]).unwrap()
+            }
+        };
+
+        let comparators = [
+            comparator(0),
+            comparator(1),
+            comparator(2),
+        ];
+        objects.sort_by(comparators[rng.gen_range(0..3)]);
+
+        let len = objects.len();
+        if len == 1 {
+            return Self {
+                left: objects[0].clone(),
+                right: objects[0].clone(),
+                bounding_box: objects[0].bounding_box().unwrap(),
+                nodes: 1,
+                depth: 1,
+            };
+        }
+
+        let (left, right, nodes, depth): (Arc<dyn Hittable>, Arc<dyn Hittable>, usize, usize) =
+            if len == 2 {
+                (objects[0].clone(), objects[1].clone(), 1, 1)
+            } else {
+                let mid = len / 2;
+                let left = PointerBvh::build_bvh(rng, &mut objects[0..mid]);
+                let right = PointerBvh::build_bvh(rng, &mut objects[mid..]);
+                let (nodes, depth) = (1 + left.nodes + right.nodes, 1 + left.depth.max(right.depth));
+                (Arc::new(left), Arc::new(right), nodes, depth)
+            };
+
+        let mut bbox = left.bounding_box().unwrap();
+        bbox.merge(&right.bounding_box().unwrap());
+        Self {
+            left, right,
+            bounding_box: bbox,
+            nodes, depth,
+        }
+    }
+}
+
+impl Hittable for PointerBvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        if !self.bounding_box.hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        if let Some(left) = self.left.hit(ray, t_min, t_max, rng) {
+            match self.right.hit(ray, t_min, left.t, rng) {
+                Some(r) => Some(r),
+                None => Some(left),
+            }
+        } else {
+            self.right.hit(ray, t_min, t_max, rng)
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounding_box)
+    }
+}
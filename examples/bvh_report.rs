@@ -0,0 +1,46 @@
+// Compares the median split and SAH BVH builders, and the random axis tree they replaced, on
+// final_scene's geometry and on the triangles of an OBJ file when one is given
+//
+//   cargo run --release --example bvh_report [model.obj]
+
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+use raytracer::vec3::Color;
+use raytracer::hittable::Hittable;
+use raytracer::bvh::{BVH, SplitMethod};
+use raytracer::material::{Material, Lambertian};
+use raytracer::texture::SolidTexture;
+use raytracer::obj::load_obj;
+
+#[path = "../benches/common/mod.rs"]
+mod common;
+
+use common::{final_scene_objects, PointerBvh};
+
+fn main() {
+    let material: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)))));
+
+    report("final_scene ground boxes and sphere cloud", &final_scene_objects(material.clone()));
+
+    if let Some(path) = env::args().nth(1) {
+        let meshes = load_obj(&path, material, false).unwrap_or_else(|e| panic!("Failed to load OBJ file {}", e));
+        let triangles: Vec<Arc<dyn Hittable>> = meshes.iter().flat_map(|(mesh, _)| mesh.triangles()).collect();
+        report(&path, &triangles);
+    }
+}
+
+fn report(name: &str, objects: &[Arc<dyn Hittable>]) {
+    println!("{}", name);
+    let start = Instant::now();
+    let original = PointerBvh::new(objects.to_vec());
+    let elapsed = start.elapsed();
+    println!("  {:<6} {} nodes, depth {}, built in {:.1} ms", "random", original.nodes(), original.depth(),
+             elapsed.as_secs_f64() * 1000.0);
+    for (label, method) in [("median", SplitMethod::Median), ("sah", SplitMethod::Sah)] {
+        let start = Instant::now();
+        let bvh = BVH::with_split_method(objects.to_vec(), method);
+        let elapsed = start.elapsed();
+        println!("  {:<6} {}, built in {:.1} ms", label, bvh.stats(), elapsed.as_secs_f64() * 1000.0);
+    }
+}
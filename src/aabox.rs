@@ -1,6 +1,7 @@
-use std::rc::Rc;
+use rand::RngCore;
+use std::sync::Arc;
 
-use super::vec3::Point3;
+use super::vec3::{Point3, Vec3};
 use super::hittable::{Hittable, HitRecord, HittableList};
 use super::rect::AARect;
 use super::material::Material;
@@ -14,17 +15,17 @@ pub struct AABox {
 }
 
 impl AABox {
-    pub fn new(min: &Point3, max: &Point3, material: Rc<dyn Material>) -> Self {
+    pub fn new(min: &Point3, max: &Point3, material: Arc<dyn Material>) -> Self {
         let mut sides = HittableList::default();
         
-        sides.add(Rc::new(AARect::new_xy(min.x(), max.x(), min.y(), max.y(), max.z(), material.clone())));
-        sides.add(Rc::new(AARect::new_xy(min.x(), max.x(), min.y(), max.y(), min.z(), material.clone())));
+        sides.add(Arc::new(AARect::new_xy(min.x(), max.x(), min.y(), max.y(), max.z(), material.clone())));
+        sides.add(Arc::new(AARect::new_xy(min.x(), max.x(), min.y(), max.y(), min.z(), material.clone())));
 
-        sides.add(Rc::new(AARect::new_xz(min.x(), max.x(), min.z(), max.z(), max.y(), material.clone())));
-        sides.add(Rc::new(AARect::new_xz(min.x(), max.x(), min.z(), max.z(), min.y(), material.clone())));
+        sides.add(Arc::new(AARect::new_xz(min.x(), max.x(), min.z(), max.z(), max.y(), material.clone())));
+        sides.add(Arc::new(AARect::new_xz(min.x(), max.x(), min.z(), max.z(), min.y(), material.clone())));
 
-        sides.add(Rc::new(AARect::new_yz(min.y(), max.y(), min.z(), max.z(), max.x(), material.clone())));
-        sides.add(Rc::new(AARect::new_yz(min.y(), max.y(), min.z(), max.z(), min.x(), material.clone())));
+        sides.add(Arc::new(AARect::new_yz(min.y(), max.y(), min.z(), max.z(), max.x(), material.clone())));
+        sides.add(Arc::new(AARect::new_yz(min.y(), max.y(), min.z(), max.z(), min.x(), material.clone())));
     
         Self {
             min: *min,
@@ -35,8 +36,19 @@ impl AABox {
 }
 
 impl Hittable for AABox {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(ray, t_min, t_max)
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let rec = self.sides.hit(ray, t_min, t_max, rng)?;
+
+        // Every side faces along its positive axis, so the sides at min face into the box. Turn
+        // them outward, front_face then tells rays entering the box from rays leaving it.
+        let axis = if rec.normal.x() != 0.0 {0} else if rec.normal.y() != 0.0 {1} else {2};
+        let mut outward_normal = Vec3::default();
+        outward_normal[axis] = if (rec.p[axis] - self.min[axis]).abs() < (rec.p[axis] - self.max[axis]).abs() {
+            -1.0
+        } else {
+            1.0
+        };
+        Some(HitRecord::new(ray, rec.t, &rec.p, &outward_normal, rec.material, rec.u, rec.v))
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -1,4 +1,4 @@
-use super::vec3::Point3;
+use super::vec3::{Point3, Vec3};
 use super::ray::Ray;
 
 #[derive(Copy, Clone, Debug)]
@@ -9,10 +9,8 @@ pub struct AABB {
 
 impl Default for AABB {
     fn default() -> Self {
-        use std::f64::MIN;
-        use std::f64::MAX;
-        Self::new(&Point3::new(MAX, MAX, MAX),
-            &Point3::new(MIN, MIN, MIN))
+        Self::new(&Point3::new(f64::MAX, f64::MAX, f64::MAX),
+            &Point3::new(f64::MIN, f64::MIN, f64::MIN))
     }
 }
 
@@ -34,17 +32,24 @@ impl AABB {
 
     pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
         let dir = ray.dir();
-        let origin = ray.origin();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        self.hit_with_inverse(&ray.origin(), &inv_dir, t_min, t_max)
+    }
+
+    // Slab test taking the reciprocal of the ray direction, so it's computed once per ray
+    // when testing many boxes
+    pub fn hit_with_inverse(&self, origin: &Point3, inv_dir: &Vec3, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
         for i in 0..3 {
-            let inv_d = 1.0 / dir[i];
-            let mut t0 = (self.min[i] - origin[i]) * inv_d;
-            let mut t1 = (self.max[i] - origin[i]) * inv_d;
-            if inv_d < 0.0 {
+            let mut t0 = (self.min[i] - origin[i]) * inv_dir[i];
+            let mut t1 = (self.max[i] - origin[i]) * inv_dir[i];
+            if inv_dir[i] < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
 
-            let t_min = if t0 > t_min {t0} else {t_min};
-            let t_max = if t1 < t_max {t1} else {t_max};
+            t_min = if t0 > t_min {t0} else {t_min};
+            t_max = if t1 < t_max {t1} else {t_max};
             if t_max <= t_min {
                 return false;
             }
@@ -65,6 +70,18 @@ impl AABB {
                                 fmax(self.max.z(), omax.z()));
     }
 
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if !self.valid() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn valid(&self) -> bool {
         self.min.x() <= self.max.x() 
         && self.min.y() <= self.max.y()
@@ -0,0 +1,528 @@
+use rand::RngCore;
+use std::fmt;
+use std::sync::Arc;
+
+use super::vec3::{Point3, Vec3};
+use super::ray::Ray;
+use super::hittable::{Hittable, HitRecord};
+use super::bbox::AABB;
+
+// Relative costs of visiting a node and of intersecting a primitive, for the surface area heuristic
+const TRAVERSAL_COST: f64 = 0.125;
+const INTERSECTION_COST: f64 = 1.0;
+// Number of centroid bins per axis that the SAH builder evaluates splits between
+const SAH_BINS: usize = 16;
+// Nodes with more primitives than this are always split by the SAH builder
+const MAX_LEAF_SIZE: usize = 4;
+// Below this depth the SAH builder switches to median splits, which bound the remaining depth
+// by log2 of the primitive count, so traversal fits in a fixed stack
+const MAX_SAH_DEPTH: usize = 32;
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SplitMethod {
+    // Binned surface area heuristic, leaves hold up to MAX_LEAF_SIZE primitives
+    #[default]
+    Sah,
+    // Sorts along the longest axis and splits at the median, one primitive per leaf
+    Median,
+}
+
+// Nodes are stored depth first in one array, the first child of an interior node directly
+// follows it, and every leaf refers to a contiguous run of objects. Objects without a bounding
+// box, like planes, can't be placed in the tree and are tested against every ray instead.
+// A BVH without objects is valid, it never hits and has no bounding box.
+pub struct BVH {
+    nodes: Vec<LinearNode>,
+    objects: Vec<Arc<dyn Hittable>>,
+    unbounded: Vec<Arc<dyn Hittable>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct LinearNode {
+    bounding_box: AABB,
+    // Index of the first object of a leaf, or of the second child of an interior node
+    offset: usize,
+    // Number of objects in a leaf, 0 for interior nodes
+    count: usize,
+    // Axis the children of an interior node were split along
+    axis: usize,
+}
+
+// Tree produced by the builders, flattened into LinearNodes afterwards
+enum BuildNode {
+    Leaf {
+        bounding_box: AABB,
+        objects: Vec<Arc<dyn Hittable>>,
+    },
+    Interior {
+        bounding_box: AABB,
+        axis: usize,
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+    },
+}
+
+// An object with its bounds cached for the build
+struct BuildPrimitive {
+    object: Arc<dyn Hittable>,
+    bounding_box: AABB,
+    centroid: Point3,
+}
+
+#[allow(dead_code)]
+impl BVH {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> Self {
+        BVH::with_split_method(objects, SplitMethod::default())
+    }
+
+    pub fn with_split_method(objects: Vec<Arc<dyn Hittable>>, method: SplitMethod) -> Self {
+        let mut primitives = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for object in objects.into_iter() {
+            match object.bounding_box() {
+                Some(bounding_box) => primitives.push(BuildPrimitive {
+                    object, bounding_box,
+                    centroid: bounding_box.centroid(),
+                }),
+                None => unbounded.push(object),
+            }
+        }
+
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            objects: Vec::with_capacity(primitives.len()),
+            unbounded,
+        };
+        if primitives.is_empty() {
+            return bvh;
+        }
+
+        let root = match method {
+            SplitMethod::Sah => build_sah(&mut primitives, 1),
+            SplitMethod::Median => build_median(&mut primitives),
+        };
+        bvh.flatten(root);
+        bvh
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            unbounded: self.unbounded.len(),
+            ..BvhStats::default()
+        };
+        if let Some(root) = self.nodes.first() {
+            self.collect_stats(0, &mut stats, 1, root.bounding_box.surface_area());
+        }
+        stats
+    }
+
+    // Appends the subtree depth first, returns the index of its root
+    fn flatten(&mut self, node: BuildNode) -> usize {
+        let index = self.nodes.len();
+        match node {
+            BuildNode::Leaf {bounding_box, objects} => {
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: self.objects.len(),
+                    count: objects.len(),
+                    axis: 0,
+                });
+                self.objects.extend(objects);
+            },
+            BuildNode::Interior {bounding_box, axis, left, right} => {
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: 0,
+                    count: 0,
+                    axis,
+                });
+                self.flatten(*left);
+                self.nodes[index].offset = self.flatten(*right);
+            },
+        }
+        index
+    }
+
+    // root_area is the surface area of the root's box, SAH costs are relative to it
+    fn collect_stats(&self, index: usize, stats: &mut BvhStats, depth: usize, root_area: f64) {
+        let node = &self.nodes[index];
+        let area_ratio = if root_area > 0.0 {node.bounding_box.surface_area() / root_area} else {1.0};
+        stats.nodes += 1;
+        stats.depth = stats.depth.max(depth);
+        if node.count > 0 {
+            stats.leaves += 1;
+            stats.primitives += node.count;
+            stats.max_leaf_size = stats.max_leaf_size.max(node.count);
+            stats.sah_cost += area_ratio * INTERSECTION_COST * node.count as f64;
+        } else {
+            stats.sah_cost += area_ratio * TRAVERSAL_COST;
+            self.collect_stats(index + 1, stats, depth + 1, root_area);
+            self.collect_stats(node.offset, stats, depth + 1, root_area);
+        }
+    }
+}
+
+impl Hittable for BVH {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut record: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+        // Unbounded objects go first, a close hit on one lets the tree cull more nodes
+        for o in self.unbounded.iter() {
+            if let Some(r) = o.hit(ray, t_min, closest_so_far, rng) {
+                closest_so_far = r.t;
+                record = Some(r);
+            }
+        }
+        if self.nodes.is_empty() {
+            return record;
+        }
+
+        let origin = ray.origin();
+        let dir = ray.dir();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        let dir_is_negative = [inv_dir.x() < 0.0, inv_dir.y() < 0.0, inv_dir.z() < 0.0];
+
+        // Far children still to visit
+        let mut stack = [0usize; TRAVERSAL_STACK_SIZE];
+        let mut stack_len = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bounding_box.hit_with_inverse(&origin, &inv_dir, t_min, closest_so_far) {
+                if node.count > 0 {
+                    for o in self.objects[node.offset..node.offset + node.count].iter() {
+                        if let Some(r) = o.hit(ray, t_min, closest_so_far, rng) {
+                            closest_so_far = r.t;
+                            record = Some(r);
+                        }
+                    }
+                } else {
+                    // Visiting the child on the ray's side of the split first shrinks closest_so_far
+                    // sooner, so the far child is more often culled
+                    let (near, far) = if dir_is_negative[node.axis] {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+        record
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|root| root.bounding_box)
+    }
+}
+
+// Shape of a built tree, to compare split methods
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BvhStats {
+    // Interior nodes and leaves
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    // Objects without a bounding box, kept out of the tree
+    pub unbounded: usize,
+    // Number of nodes on the longest path from the root to a leaf
+    pub depth: usize,
+    pub max_leaf_size: usize,
+    // Expected cost of tracing a ray that hits the root's box, in units of primitive intersections
+    pub sah_cost: f64,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nodes, {} leaves, {} primitives, {} unbounded, depth {}, max leaf size {}, SAH cost {:.2}",
+                self.nodes, self.leaves, self.primitives, self.unbounded, self.depth, self.max_leaf_size, self.sah_cost)
+    }
+}
+
+fn bounds(primitives: &[BuildPrimitive]) -> AABB {
+    let mut bounding_box = AABB::default();
+    for p in primitives.iter() {
+        bounding_box.merge(&p.bounding_box);
+    }
+    bounding_box
+}
+
+fn make_leaf(primitives: &[BuildPrimitive], bounding_box: AABB) -> BuildNode {
+    BuildNode::Leaf {
+        bounding_box,
+        objects: primitives.iter().map(|p| p.object.clone()).collect(),
+    }
+}
+
+fn make_interior(left: BuildNode, right: BuildNode, axis: usize, bounding_box: AABB) -> BuildNode {
+    BuildNode::Interior {
+        bounding_box, axis,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+// depth is that of the node being built, the root is at depth 1
+fn build_sah(primitives: &mut [BuildPrimitive], depth: usize) -> BuildNode {
+    if depth >= MAX_SAH_DEPTH {
+        return build_median(primitives);
+    }
+
+    let bounding_box = bounds(primitives);
+    let count = primitives.len();
+    if count <= 1 {
+        return make_leaf(primitives, bounding_box);
+    }
+
+    let mut centroid_bounds = AABB::default();
+    for p in primitives.iter() {
+        centroid_bounds.merge(&AABB::new(&p.centroid, &p.centroid));
+    }
+
+    let area = bounding_box.surface_area();
+    let inv_area = if area > 0.0 {1.0 / area} else {0.0};
+    // (cost, axis, last bin of the left side)
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        let min = centroid_bounds.min()[axis];
+        let extent = centroid_bounds.max()[axis] - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_counts = [0usize; SAH_BINS];
+        let mut bin_bounds = [AABB::default(); SAH_BINS];
+        for p in primitives.iter() {
+            let bin = bin_index(p.centroid[axis], min, extent);
+            bin_counts[bin] += 1;
+            bin_bounds[bin].merge(&p.bounding_box);
+        }
+
+        // Sweep from the right to get the area and count of every right side
+        let mut right_areas = [0.0; SAH_BINS];
+        let mut right_counts = [0usize; SAH_BINS];
+        let mut accumulated = AABB::default();
+        let mut accumulated_count = 0;
+        for bin in (1..SAH_BINS).rev() {
+            accumulated.merge(&bin_bounds[bin]);
+            accumulated_count += bin_counts[bin];
+            right_areas[bin - 1] = accumulated.surface_area();
+            right_counts[bin - 1] = accumulated_count;
+        }
+
+        let mut accumulated = AABB::default();
+        let mut accumulated_count = 0;
+        for bin in 0..SAH_BINS - 1 {
+            accumulated.merge(&bin_bounds[bin]);
+            accumulated_count += bin_counts[bin];
+            if accumulated_count == 0 || right_counts[bin] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + INTERSECTION_COST * inv_area
+                * (accumulated_count as f64 * accumulated.surface_area() + right_counts[bin] as f64 * right_areas[bin]);
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+
+    let leaf_cost = INTERSECTION_COST * count as f64;
+    let (mid, axis) = match best {
+        Some((cost, _, _)) if cost >= leaf_cost && count <= MAX_LEAF_SIZE => {
+            return make_leaf(primitives, bounding_box);
+        },
+        Some((_, axis, split_bin)) => {
+            let min = centroid_bounds.min()[axis];
+            let extent = centroid_bounds.max()[axis] - min;
+            (partition(primitives, |p| bin_index(p.centroid[axis], min, extent) <= split_bin), axis)
+        },
+        // Every centroid is at the same point, so no split separates the boxes
+        None if count <= MAX_LEAF_SIZE => return make_leaf(primitives, bounding_box),
+        None => (count / 2, 0),
+    };
+
+    let (left, right) = primitives.split_at_mut(mid);
+    make_interior(build_sah(left, depth + 1), build_sah(right, depth + 1), axis, bounding_box)
+}
+
+fn bin_index(value: f64, min: f64, extent: f64) -> usize {
+    (((value - min) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+// Moves the primitives matching the predicate to the front, returns how many there are
+fn partition<F: Fn(&BuildPrimitive) -> bool>(primitives: &mut [BuildPrimitive], predicate: F) -> usize {
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if predicate(&primitives[i]) {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+// Splits along the longest axis of the objects' bounds, so the same objects always build
+// the same tree
+fn build_median(primitives: &mut [BuildPrimitive]) -> BuildNode {
+    let bounding_box = bounds(primitives);
+    if primitives.len() <= 1 {
+        return make_leaf(primitives, bounding_box);
+    }
+
+    let extent = bounding_box.max() - bounding_box.min();
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+    primitives.sort_by(|a, b| a.bounding_box.min()[axis].partial_cmp(&b.bounding_box.min()[axis]).unwrap());
+
+    let mid = primitives.len() / 2;
+    let (left, right) = primitives.split_at_mut(mid);
+    make_interior(build_median(left), build_median(right), axis, bounding_box)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::aabox::AABox;
+    use crate::hittable::HittableList;
+    use crate::material::{Material, Lambertian};
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidTexture;
+    use crate::triangle::Triangle;
+    use crate::vec3::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.5, 0.5, 0.5)))))
+    }
+
+    fn random_point(rng: &mut StdRng, extent: f64) -> Point3 {
+        Point3::new(rng.gen_range(-extent..extent), rng.gen_range(-extent..extent), rng.gen_range(-extent..extent))
+    }
+
+    // Overlapping spheres, boxes and triangles of very different sizes, with a cluster of spheres
+    // sharing a centroid that no split can separate
+    fn random_objects(rng: &mut StdRng) -> Vec<Arc<dyn Hittable>> {
+        let material = material();
+        let mut objects = Vec::<Arc<dyn Hittable>>::new();
+        for _ in 0..150 {
+            let center = random_point(rng, 10.0);
+            objects.push(Arc::new(Sphere::new(&center, rng.gen_range(0.05..2.0), material.clone())));
+        }
+        for _ in 0..100 {
+            let min = random_point(rng, 10.0);
+            let size = Vec3::new(rng.gen_range(0.1..3.0), rng.gen_range(0.1..3.0), rng.gen_range(0.1..3.0));
+            objects.push(Arc::new(AABox::new(&min, &(min + size), material.clone())));
+        }
+        for _ in 0..150 {
+            let v0 = random_point(rng, 10.0);
+            let v1 = v0 + random_point(rng, 2.0);
+            let v2 = v0 + random_point(rng, 2.0);
+            objects.push(Arc::new(Triangle::new(&v0, &v1, &v2, material.clone())));
+        }
+        for i in 0..20 {
+            objects.push(Arc::new(Sphere::new(&Point3::new(3.0, 3.0, 3.0), 0.1 * (i + 1) as f64, material.clone())));
+        }
+        objects
+    }
+
+    // t and hit point of the closest hit
+    fn closest_hit(object: &dyn Hittable, ray: &Ray, rng: &mut dyn RngCore) -> Option<(f64, Point3)> {
+        object.hit(ray, 0.001, f64::INFINITY, rng).map(|rec| (rec.t, rec.p))
+    }
+
+    #[test]
+    fn bvh_finds_the_same_closest_hit_as_a_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let objects = random_objects(&mut rng);
+        let mut list = HittableList::default();
+        for object in objects.iter() {
+            list.add(object.clone());
+        }
+
+        for method in [SplitMethod::Sah, SplitMethod::Median] {
+            let bvh = BVH::with_split_method(objects.clone(), method);
+            let mut hits = 0;
+            for _ in 0..5000 {
+                let origin = random_point(&mut rng, 15.0);
+                let mut dir = random_point(&mut rng, 1.0);
+                // Axis aligned rays have infinite reciprocals in the slab test
+                if rng.gen_bool(0.1) {
+                    dir = Vec3::new(0.0, 0.0, if dir.z() < 0.0 {-1.0} else {1.0});
+                }
+                let ray = Ray::new(&origin, &dir, 0.0);
+                let expected = closest_hit(&list, &ray, &mut rng);
+                let actual = closest_hit(&bvh, &ray, &mut rng);
+                match (expected, actual) {
+                    (Some((t0, p0)), Some((t1, p1))) => {
+                        assert_eq!(t0, t1, "{:?} split, ray from {:?} along {:?}", method, origin, dir);
+                        assert!((p0 - p1).near_zero());
+                        hits += 1;
+                    },
+                    (None, None) => {},
+                    _ => panic!("{:?} split, ray from {:?} along {:?}: {:?} against {:?}",
+                                method, origin, dir, expected.map(|h| h.0), actual.map(|h| h.0)),
+                }
+            }
+            // Enough rays should hit something for the test to prove anything
+            assert!(hits > 1000, "only {} of the rays hit", hits);
+        }
+    }
+
+    #[test]
+    fn empty_bvh_never_hits_and_has_no_bounding_box() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for method in [SplitMethod::Sah, SplitMethod::Median] {
+            let bvh = BVH::with_split_method(Vec::new(), method);
+            assert!(bvh.bounding_box().is_none());
+            assert_eq!(bvh.stats().nodes, 0);
+            let ray = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            assert!(bvh.hit(&ray, 0.001, f64::INFINITY, &mut rng).is_none());
+        }
+    }
+
+    #[test]
+    fn unbounded_objects_are_hit_outside_the_tree() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let floor: Arc<dyn Hittable> = Arc::new(Plane::new(&Point3::new(0.0, -1.0, 0.0), &Vec3::new(0.0, 1.0, 0.0), material()));
+        let wall: Arc<dyn Hittable> = Arc::new(Plane::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), material()));
+        let down = Ray::new(&Point3::default(), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let ahead = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let up = Ray::new(&Point3::default(), &Vec3::new(0.0, 1.0, 0.0), 0.0);
+
+        // Only unbounded objects, there is no tree at all
+        let bvh = BVH::new(vec![floor.clone(), wall.clone()]);
+        assert!(bvh.bounding_box().is_none());
+        assert_eq!(bvh.stats().nodes, 0);
+        assert_eq!(bvh.stats().unbounded, 2);
+        assert_eq!(closest_hit(&bvh, &down, &mut rng).map(|h| h.0), Some(1.0));
+        assert_eq!(closest_hit(&bvh, &ahead, &mut rng).map(|h| h.0), Some(5.0));
+        assert!(closest_hit(&bvh, &up, &mut rng).is_none());
+
+        // A bounded object in front of an unbounded one still wins, and the BVH as a whole is unbounded
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(&Point3::new(0.0, 0.0, -2.0), 0.5, material()));
+        let bvh = BVH::new(vec![floor, wall, sphere]);
+        assert!(bvh.bounding_box().is_none());
+        assert_eq!(closest_hit(&bvh, &ahead, &mut rng).map(|h| h.0), Some(1.5));
+        assert_eq!(closest_hit(&bvh, &down, &mut rng).map(|h| h.0), Some(1.0));
+    }
+}
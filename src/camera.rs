@@ -1,4 +1,4 @@
-use rand::{self,Rng};
+use rand::{Rng, RngCore};
 
 use super::vec3::{Point3, Vec3};
 use super::ray::Ray;
@@ -17,6 +17,7 @@ pub struct Camera {
 
 #[allow(dead_code)]
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(look_from: &Point3, look_at: &Point3, up: &Vec3, 
                 fov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64, shutter_duration: f64) -> Self {
         let fov = fov.to_radians() / 2.0;
@@ -42,24 +43,31 @@ impl Camera {
         }
     }
 
-    pub fn gen_ray(&self, u: f64, v: f64) -> Ray {
-        let mut rng = rand::thread_rng();
-        let offset = random_in_unit_disk() * (self.aperture * 0.5);
+    // Draws the lens position, then the time unless the shutter is closed
+    pub fn gen_ray(&self, u: f64, v: f64, rng: &mut dyn RngCore) -> Ray {
+        let offset = random_in_unit_disk(rng) * (self.aperture * 0.5);
         let offset = self.u * offset.x() + self.v * offset.y();
         // (self.lower_left_corner + self.horizontal * u + self.vertical * v) is a point in the focus plane,
         // so if the ray hit a point in focus plane then it will be focus, otherwise it will be defocus
         Ray::new(&(self.origin + offset), 
                 &(self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin - offset).unit_vector(),
-                rng.gen_range(0.0..self.shutter_duration))
+                if self.shutter_duration > 0.0 {rng.gen_range(0.0..self.shutter_duration)} else {0.0})
     }
 }
 
-fn random_in_unit_disk() -> Point3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let p = Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-        if p.length_squared() < 1.0 {
-            break p
-        }
+// Shirley and Chiu's concentric mapping of the unit square to the disk, which keeps
+// stratified samples stratified
+fn random_in_unit_disk(rng: &mut dyn RngCore) -> Point3 {
+    let a: f64 = rng.gen_range(-1.0..1.0);
+    let b: f64 = rng.gen_range(-1.0..1.0);
+    if a == 0.0 && b == 0.0 {
+        return Point3::default();
     }
-}
\ No newline at end of file
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b))
+    };
+    Point3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
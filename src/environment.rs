@@ -0,0 +1,187 @@
+use image::{self, io::Reader as ImageReader, ImageResult, ImageError};
+use image::codecs::hdr::HdrDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::vec3::{Vec3, Color};
+use super::tonemap::srgb_to_linear;
+
+// Light arriving from infinitely far away, seen by rays that leave the scene
+pub trait Environment: Send + Sync {
+    fn value(&self, dir: &Vec3) -> Color;
+}
+
+pub struct SolidBackground {
+    color: Color,
+}
+
+#[allow(dead_code)]
+impl SolidBackground {
+    pub fn new(color: &Color, intensity: f64) -> Self {
+        Self {
+            color: *color * intensity,
+        }
+    }
+}
+
+impl Default for SolidBackground {
+    fn default() -> Self {
+        Self::new(&Color::default(), 1.0)
+    }
+}
+
+impl Environment for SolidBackground {
+    fn value(&self, _dir: &Vec3) -> Color {
+        self.color
+    }
+}
+
+// Vertical blend from the horizon color straight down to the zenith color straight up
+pub struct GradientSky {
+    horizon: Color,
+    zenith: Color,
+    rotation: Rotation,
+}
+
+#[allow(dead_code)]
+impl GradientSky {
+    // rotation is in degrees around the x, y and z axes
+    pub fn new(horizon: &Color, zenith: &Color, rotation: &Vec3, intensity: f64) -> Self {
+        Self {
+            horizon: *horizon * intensity,
+            zenith: *zenith * intensity,
+            rotation: Rotation::new(rotation),
+        }
+    }
+}
+
+impl Default for GradientSky {
+    fn default() -> Self {
+        Self::new(&Color::new(1.0, 1.0, 1.0), &Color::new(0.5, 0.7, 1.0), &Vec3::default(), 1.0)
+    }
+}
+
+impl Environment for GradientSky {
+    fn value(&self, dir: &Vec3) -> Color {
+        let dir = self.rotation.inverse_apply(&dir.unit_vector());
+        let t = 0.5 * (dir.y() + 1.0);
+        self.horizon * (1.0 - t) + self.zenith * t
+    }
+}
+
+// Equirectangular environment map, Radiance .hdr files are read as linear radiance
+// and every other format is treated as sRGB encoded
+pub struct ImageEnvironment {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    rotation: Rotation,
+    intensity: f64,
+}
+
+#[allow(dead_code)]
+impl ImageEnvironment {
+    // rotation is in degrees around the x, y and z axes
+    pub fn new<P: AsRef<Path>>(path: P, rotation: &Vec3, intensity: f64) -> ImageResult<Self> {
+        let (width, height, pixels) = ImageEnvironment::load(path.as_ref())?;
+        if width == 0 || height == 0 {
+            return Err(ImageError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                                            "empty environment map")));
+        }
+
+        Ok(Self {
+            width, height, pixels,
+            rotation: Rotation::new(rotation),
+            intensity,
+        })
+    }
+
+    fn load(path: &Path) -> ImageResult<(usize, usize, Vec<Color>)> {
+        let is_hdr = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("hdr"))
+            .unwrap_or(false);
+
+        if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?.iter()
+                .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                .collect();
+            Ok((metadata.width as usize, metadata.height as usize, pixels))
+        } else {
+            let img = ImageReader::open(path)?.decode()?.to_rgb8();
+            const SCALE: f64 = 1.0 / 255.0;
+            let pixels = img.pixels()
+                .map(|p| Color::new(srgb_to_linear(p[0] as f64 * SCALE),
+                                    srgb_to_linear(p[1] as f64 * SCALE),
+                                    srgb_to_linear(p[2] as f64 * SCALE)))
+                .collect();
+            Ok((img.width() as usize, img.height() as usize, pixels))
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Environment for ImageEnvironment {
+    fn value(&self, dir: &Vec3) -> Color {
+        use std::f64::consts::PI;
+        use std::f64::consts::FRAC_1_PI;
+
+        // Same parameterization as the sphere uv, v = 0 looks straight down
+        let dir = self.rotation.inverse_apply(&dir.unit_vector());
+        let theta = (-dir.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-dir.z()).atan2(dir.x()) + PI;
+        let u = phi * 0.5 * FRAC_1_PI;
+        let v = theta * FRAC_1_PI;
+
+        // Bilinear filtering, wrapping around horizontally
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+        let wrap = |x: f64| (x as i64).rem_euclid(self.width as i64) as usize;
+        let limit = |y: f64| (y.max(0.0) as usize).min(self.height - 1);
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let (y0, y1) = (limit(y0), limit(y0 + 1.0));
+
+        let top = self.pixel(x0, y0) * (1.0 - fx) + self.pixel(x1, y0) * fx;
+        let bottom = self.pixel(x0, y1) * (1.0 - fx) + self.pixel(x1, y1) * fx;
+        (top * (1.0 - fy) + bottom * fy) * self.intensity
+    }
+}
+
+// Rotation around the x, then y, then z axis
+struct Rotation {
+    m: [[f64; 3]; 3],
+}
+
+impl Rotation {
+    fn new(degrees: &Vec3) -> Self {
+        let (sx, cx) = degrees.x().to_radians().sin_cos();
+        let (sy, cy) = degrees.y().to_radians().sin_cos();
+        let (sz, cz) = degrees.z().to_radians().sin_cos();
+        // Rz * Ry * Rx
+        Self {
+            m: [
+                [cy * cz, sx * sy * cz - cx * sz, cx * sy * cz + sx * sz],
+                [cy * sz, sx * sy * sz + cx * cz, cx * sy * sz - sx * cz],
+                [-sy, sx * cy, cx * cy],
+            ]
+        }
+    }
+
+    // Rotation matrices are orthogonal, so the inverse is the transpose
+    fn inverse_apply(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(m[0][0] * v.x() + m[1][0] * v.y() + m[2][0] * v.z(),
+                m[0][1] * v.x() + m[1][1] * v.y() + m[2][1] * v.z(),
+                m[0][2] * v.x() + m[1][2] * v.y() + m[2][2] * v.z())
+    }
+}
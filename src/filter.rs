@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    // Equal weight within the radius, with radius 0.5 every sample only counts for its own pixel
+    #[default]
+    Box,
+    // Weight falls linearly to zero at the radius
+    Tent,
+    // Gaussian with a standard deviation of a third of the radius, shifted to reach zero at the radius
+    Gaussian,
+    // Mitchell-Netravali cubic with B = C = 1/3
+    Mitchell,
+    // Sinc windowed by a sinc stretched to the radius
+    Lanczos,
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "box" => Ok(FilterKind::Box),
+            "tent" | "triangle" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" | "mitchell-netravali" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!("Unknown filter {}", s)),
+        }
+    }
+}
+
+impl FilterKind {
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 2.0,
+        }
+    }
+}
+
+// Pixel reconstruction filter, every sample adds to the pixels whose centers are within radius
+// of it along both axes, weighted by the separable filter. Mitchell and Lanczos have negative lobes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    kind: FilterKind,
+    // In pixels
+    radius: f64,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::with_default_radius(FilterKind::default())
+    }
+}
+
+#[allow(dead_code)]
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Self {
+        assert!(radius > 0.0, "filter radius must be positive");
+        Self {
+            kind, radius,
+        }
+    }
+
+    pub fn with_default_radius(kind: FilterKind) -> Self {
+        Self::new(kind, kind.default_radius())
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    // Weight of a sample at offset (dx, dy) in pixels from a pixel's center
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(self.radius)).max(0.0)
+            },
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+}
+
+// Mitchell-Netravali cubic over [0, 2]
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    if x > 1.0 {
+        ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)) / 6.0
+    } else {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)) / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
@@ -0,0 +1,37 @@
+use super::vec3::Color;
+use super::framebuffer::Framebuffer;
+use super::tonemap::srgb_to_linear;
+
+// sRGB stops of the color ramp, evenly spaced from no samples to the most samples
+const RAMP: [[f64; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.35, 0.05, 0.55],
+    [0.85, 0.2, 0.25],
+    [1.0, 0.75, 0.1],
+    [1.0, 1.0, 1.0],
+];
+
+// Visualizes per pixel sample counts, in row order, from black for none through purple, red and
+// yellow to white for max_count. Colors are linear, ready to be saved without tone mapping.
+pub fn sample_heatmap(counts: &[usize], width: usize, height: usize, max_count: usize) -> Framebuffer {
+    assert_eq!(counts.len(), width * height, "expected one sample count per pixel");
+
+    let mut image = Framebuffer::new(width, height);
+    let scale = if max_count > 0 {1.0 / max_count as f64} else {0.0};
+    for (pixel, count) in image.pixels_mut().iter_mut().zip(counts.iter()) {
+        *pixel = ramp((*count as f64 * scale).min(1.0));
+    }
+    image
+}
+
+fn ramp(t: f64) -> Color {
+    let position = t * (RAMP.len() - 1) as f64;
+    let index = (position as usize).min(RAMP.len() - 2);
+    let f = position - index as f64;
+    let (a, b) = (RAMP[index], RAMP[index + 1]);
+    Color::new(
+        srgb_to_linear(a[0] + (b[0] - a[0]) * f),
+        srgb_to_linear(a[1] + (b[1] - a[1]) * f),
+        srgb_to_linear(a[2] + (b[2] - a[2]) * f),
+    )
+}
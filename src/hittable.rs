@@ -1,16 +1,27 @@
 
-use rand::{self,Rng};
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 use super::ray::Ray;
 use super::vec3::{Point3, Vec3};
 use super::material::Material;
 use super::bbox::AABB;
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Send + Sync {
+    // rng supplies the random numbers of objects that intersect stochastically, like participating media
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord>;
     fn bounding_box(&self) -> Option<AABB> {
         None
     }
+
+    // Density, by solid angle, of random(origin) returning a direction parallel to dir
+    fn pdf_value(&self, _origin: &Point3, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    // Returns a random direction from origin towards the object
+    fn random(&self, _origin: &Point3, _rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
 pub struct HitRecord {
@@ -18,13 +29,13 @@ pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub front_face: bool,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
     pub u: f64,
     pub v: f64,
 }
 
 impl HitRecord {
-    pub fn new(ray: &Ray, t: f64, p: &Point3, outward_normal: &Vec3, material: Rc<dyn Material>, u: f64, v: f64) -> Self {
+    pub fn new(ray: &Ray, t: f64, p: &Point3, outward_normal: &Vec3, material: Arc<dyn Material>, u: f64, v: f64) -> Self {
         let front_face = ray.dir().dot(*outward_normal) < 0.0;
         Self {
             t,
@@ -39,12 +50,12 @@ impl HitRecord {
 
 #[derive(Default)]
 pub struct HittableList {
-    objects: Vec<Rc<dyn Hittable>>,
+    objects: Vec<Arc<dyn Hittable>>,
 }
 
 #[allow(dead_code)]
 impl HittableList {
-    pub fn new(object: Rc<dyn Hittable>) -> Self {
+    pub fn new(object: Arc<dyn Hittable>) -> Self {
         let mut ret = Self {
             objects: Vec::new()
         };
@@ -52,21 +63,29 @@ impl HittableList {
         ret
     }
 
-    pub fn add(&mut self, object: Rc<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
     }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let mut record: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
         for o in self.objects.iter() {
-            if let Some(r) = o.hit(ray, t_min, closest_so_far) {
+            if let Some(r) = o.hit(ray, t_min, closest_so_far, rng) {
                 closest_so_far = r.t;
                 record = Some(r);
             }
@@ -75,7 +94,12 @@ impl Hittable for HittableList {
         record
     }
 
+    // An empty list has nothing to bound, like an unbounded object it has no box
     fn bounding_box(&self) -> Option<AABB> {
+        if self.objects.is_empty() {
+            return None;
+        }
+
         let mut aabb = AABB::default();
 
         for o in self.objects.iter() {
@@ -88,79 +112,22 @@ impl Hittable for HittableList {
 
         Some(aabb)
     }
-}
-
-pub struct BVH {
-    left: Rc<dyn Hittable>,
-    right: Rc<dyn Hittable>,
-    bounding_box: AABB,
-}
-
-impl BVH {
-    pub fn new(mut objects: Vec<Rc<dyn Hittable>>) -> Self {
-        let mut rng = rand::thread_rng();
-        BVH::build_bvh(&mut rng, &mut objects[..])
-    }
-
-    fn build_bvh<R: Rng>(rng: &mut R, objects: &mut [Rc<dyn Hittable>]) -> Self {
-        let comparator = |axis| {
-            move |a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>| {
-                a.bounding_box().unwrap().min()[axis].partial_cmp(&b.bounding_box().unwrap().min()[axis]).unwrap()
-            }
-        };
 
-        let comparators = [
-            comparator(0),
-            comparator(1),
-            comparator(2),
-        ];
-        objects.sort_by(comparators[rng.gen_range(0..3)]);
-
-        let len = objects.len();
-        if len == 1 {
-            return Self {
-                left: objects[0].clone(),
-                right: objects[0].clone(),
-                bounding_box: objects[0].bounding_box().unwrap(),
-            };
-        } 
-        
-        let (left, right): (Rc<dyn Hittable>, Rc<dyn Hittable>) = 
-            if len == 2 {
-                (objects[0].clone(), objects[1].clone())
-            } else {
-                let mid = len / 2;
-                let left = Rc::new(BVH::build_bvh(rng, &mut objects[0..mid]));
-                let right = Rc::new(BVH::build_bvh(rng, &mut objects[mid..]));
-                (left, right)
-            };
-        
-        let mut bbox = left.bounding_box().unwrap();
-        bbox.merge(&right.bounding_box().unwrap());
-        Self {
-            left, right,
-            bounding_box: bbox,
+    // Picks an object uniformly, so the density is the average of all objects' densities
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
         }
-    }
-}
 
-impl Hittable for BVH {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.bounding_box.hit(ray, t_min, t_max) {
-            return None;
-        }
+        let sum: f64 = self.objects.iter().map(|o| o.pdf_value(origin, dir)).sum();
+        sum / self.objects.len() as f64
+    }
 
-        if let Some(left) = self.left.hit(ray, t_min, t_max) {
-            match self.right.hit(ray, t_min, left.t) {
-                Some(r) => Some(r),
-                None => Some(left),
-            }
-        } else {
-            self.right.hit(ray, t_min, t_max)
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
         }
-    }
 
-    fn bounding_box(&self) -> Option<AABB> {
-        Some(self.bounding_box)
+        self.objects[rng.gen_range(0..self.objects.len())].random(origin, rng)
     }
-}
\ No newline at end of file
+}
@@ -5,86 +5,647 @@ pub mod sphere;
 pub mod camera;
 pub mod material;
 pub mod bbox;
+pub mod bvh;
 pub mod texture;
 pub mod rect;
 pub mod aabox;
 pub mod medium;
+pub mod framebuffer;
+pub mod output;
+pub mod tonemap;
+pub mod environment;
+pub mod scene;
+pub mod onb;
+pub mod triangle;
+pub mod obj;
+pub mod transform;
+pub mod perlin;
+pub mod scene_file;
+pub mod plane;
+pub mod heatmap;
+pub mod sampler;
+pub mod filter;
+pub mod microfacet;
+pub mod spectrum;
 
-use rand::{self,Rng};
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
 use pbr::ProgressBar;
-use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
-use vec3::Color;
+use vec3::{Color, Vec3};
 use ray::Ray;
-use hittable::Hittable;
-use camera::Camera;
+use hittable::{Hittable, HitRecord};
+use scene::Scene;
+use framebuffer::Framebuffer;
+use sampler::{Sampler, SamplerKind, PixelSample};
+use filter::Filter;
 
-pub fn render<T: Hittable, W: Write>(world: &T, camera: &Camera, out: &mut W,
-                            image_width: usize, image_height: usize, 
-                            samplers_per_pixel: usize, max_depth: usize) {
-    // Render
-    let background_color = Color::default();
+// Edge length in pixels of the square tiles handed out to render workers
+const TILE_SIZE: usize = 16;
+// Adaptive sampling measures error relative to at least this luminance, so nearly black pixels
+// don't need a vanishing absolute error to converge
+const MIN_ERROR_LUMINANCE: f64 = 0.01;
+
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samplers_per_pixel: usize,
+    pub max_depth: usize,
+    // Number of worker threads, 0 means one per available core
+    pub threads: usize,
+    // The same seed, scene and settings always render the same image
+    pub seed: u64,
+    // When set, samplers_per_pixel is the most samples a pixel takes
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+}
+
+// Stops sampling a pixel once the standard error of its mean luminance, relative to that mean,
+// falls below error_threshold
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptiveSampling {
+    // Samples every pixel takes before it may stop, so the variance estimate can be trusted
+    pub min_samples: usize,
+    pub error_threshold: f64,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            error_threshold: 0.01,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            image_width: 400,
+            image_height: 225,
+            samplers_per_pixel: 500,
+            max_depth: 50,
+            threads: 0,
+            seed: 0,
+            adaptive: None,
+            sampler: SamplerKind::default(),
+            filter: Filter::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    fn worker_count(&self) -> usize {
+        if self.threads > 0 {
+            self.threads
+        } else {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        }
+    }
+}
+
+// A rectangular block of the image, in image coordinates where row 0 is the bottom row
+#[derive(Copy, Clone, Debug)]
+struct Tile {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+fn make_tiles(image_width: usize, image_height: usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y0 in (0..image_height).step_by(TILE_SIZE) {
+        for x0 in (0..image_width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0, y0,
+                x1: (x0 + TILE_SIZE).min(image_width),
+                y1: (y0 + TILE_SIZE).min(image_height),
+            });
+        }
+    }
+    tiles
+}
+
+// Renders the scene into a framebuffer of linear, unclamped colors
+pub fn render(scene: &Scene, settings: &RenderSettings) -> Framebuffer {
+    render_with_sample_counts(scene, settings).0
+}
+
+// Like render, also returns the number of samples every pixel took, in the framebuffer's row order
+pub fn render_with_sample_counts(scene: &Scene, settings: &RenderSettings) -> (Framebuffer, Vec<usize>) {
+    let image_width = settings.image_width;
+    let image_height = settings.image_height;
+
+    let tiles = make_tiles(image_width, image_height);
+    let sampler = settings.sampler.create(settings.seed, settings.samplers_per_pixel);
+    let next_tile = AtomicUsize::new(0);
+    let mut films: Vec<Option<TileFilm>> = tiles.iter().map(|_| None).collect();
 
     let mut pb = ProgressBar::new((image_width * image_height) as u64);
     pb.message("Rendering ");
-    // Image width height
-    out.write_fmt(format_args!("P3 {} {}\n", image_width, image_height)).unwrap();
-    // 255 for max color
-    out.write_fmt(format_args!("{}\n", 255)).unwrap(); 
-    // RGB triplets
-    let mut rng = rand::thread_rng();
-    let width_factor: f64 = 1.0 / (image_width as f64 - 1.0);
-    let height_factor: f64 = 1.0 / (image_height as f64 - 1.0);
-    for j in (0..image_height).rev() {
-        let j = j as f64;
-        for i in 0..image_width {
-            let i = i as f64;
-            let mut color = Color::default();
-            for _ in 0..samplers_per_pixel {
-                let u = (i + rng.gen_range(0.0..1.0)) * width_factor;
-                let v = (j + rng.gen_range(0.0..1.0)) * height_factor;
-                let ray = camera.gen_ray(u, v);
-                color += ray_color(&ray, &background_color, world, max_depth);
+    thread::scope(|s| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..settings.worker_count() {
+            let sender = sender.clone();
+            let tiles = &tiles;
+            let sampler = sampler.as_ref();
+            let next_tile = &next_tile;
+            s.spawn(move || {
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+                    let film = render_tile(scene, settings, sampler, &tiles[index]);
+                    if sender.send((index, film)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (index, film) in receiver {
+            pb.add(film.sample_counts.len() as u64);
+            films[index] = Some(film);
+        }
+    });
+    pb.finish();
+
+    develop(films.into_iter().flatten(), image_width, image_height)
+}
+
+// Merges the films of all tiles into the image and its sample counts, both in the framebuffer's
+// row order. Films overlap where filters reach past their tile, merging them in tile order keeps
+// the sums independent of which tile finished first.
+fn develop<I: IntoIterator<Item = TileFilm>>(films: I, image_width: usize, image_height: usize) -> (Framebuffer, Vec<usize>) {
+    let mut sums = vec![Color::default(); image_width * image_height];
+    let mut weights = vec![0.0; image_width * image_height];
+    let mut box_sums = vec![Color::default(); image_width * image_height];
+    let mut sample_counts = vec![0; image_width * image_height];
+    for film in films {
+        film.merge(image_width, &mut sums, &mut weights, &mut box_sums, &mut sample_counts);
+    }
+
+    // Image rows are stored from the top
+    let mut image = Framebuffer::new(image_width, image_height);
+    let mut flipped_counts = vec![0; image_width * image_height];
+    for j in 0..image_height {
+        let y = image_height - 1 - j;
+        for x in 0..image_width {
+            let index = j * image_width + x;
+            // The negative lobes of Mitchell and Lanczos filters can outweigh a pixel's own samples,
+            // when its neighbours took many more of them, then the pixel keeps its box filtered mean
+            let color = if weights[index] > 0.0 {
+                sums[index] / weights[index]
+            } else if sample_counts[index] > 0 {
+                box_sums[index] / sample_counts[index] as f64
+            } else {
+                Color::default()
+            };
+            image.set(x, y, &color);
+            flipped_counts[y * image_width + x] = sample_counts[index];
+        }
+    }
+
+    (image, flipped_counts)
+}
+
+// Filtered samples of a tile, covering the tile and the margin around it that the filter
+// reaches, clipped to the image. Coordinates are image coordinates, row 0 at the bottom.
+struct TileFilm {
+    tile: Tile,
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    // Unweighted sum and number of the samples taken in each of the tile's own pixels
+    box_sums: Vec<Color>,
+    sample_counts: Vec<usize>,
+}
+
+impl TileFilm {
+    fn new(tile: &Tile, filter: &Filter, image_width: usize, image_height: usize) -> Self {
+        // Samples lie within their pixel, so they reach pixel centers up to radius + 0.5 away
+        let margin = (filter.radius() - 0.5).max(0.0).ceil() as usize;
+        let x0 = tile.x0.saturating_sub(margin);
+        let y0 = tile.y0.saturating_sub(margin);
+        let x1 = (tile.x1 + margin).min(image_width);
+        let y1 = (tile.y1 + margin).min(image_height);
+        let size = (x1 - x0) * (y1 - y0);
+        Self {
+            tile: *tile,
+            x0, y0, x1, y1,
+            sums: vec![Color::default(); size],
+            weights: vec![0.0; size],
+            box_sums: vec![Color::default(); (tile.x1 - tile.x0) * (tile.y1 - tile.y0)],
+            sample_counts: vec![0; (tile.x1 - tile.x0) * (tile.y1 - tile.y0)],
+        }
+    }
+
+    // Adds a sample at continuous image position (x, y), which lies in one of the tile's pixels,
+    // to every pixel the filter reaches
+    fn add_sample(&mut self, filter: &Filter, x: f64, y: f64, color: &Color) {
+        let tile_width = self.tile.x1 - self.tile.x0;
+        self.box_sums[(y as usize - self.tile.y0) * tile_width + x as usize - self.tile.x0] += *color;
+
+        let radius = filter.radius();
+        let px0 = ((x - 0.5 - radius).ceil().max(0.0) as usize).max(self.x0);
+        let py0 = ((y - 0.5 - radius).ceil().max(0.0) as usize).max(self.y0);
+        let px1 = ((x - 0.5 + radius).floor().max(0.0) as usize + 1).min(self.x1);
+        let py1 = ((y - 0.5 + radius).floor().max(0.0) as usize + 1).min(self.y1);
+        let width = self.x1 - self.x0;
+        for py in py0..py1 {
+            for px in px0..px1 {
+                let weight = filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight != 0.0 {
+                    let index = (py - self.y0) * width + px - self.x0;
+                    self.sums[index] += *color * weight;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    fn merge(&self, image_width: usize, sums: &mut [Color], weights: &mut [f64], box_sums: &mut [Color],
+             sample_counts: &mut [usize]) {
+        let width = self.x1 - self.x0;
+        for y in self.y0..self.y1 {
+            for x in self.x0..self.x1 {
+                let index = (y - self.y0) * width + x - self.x0;
+                sums[y * image_width + x] += self.sums[index];
+                weights[y * image_width + x] += self.weights[index];
+            }
+        }
+
+        let tile_width = self.tile.x1 - self.tile.x0;
+        for y in self.tile.y0..self.tile.y1 {
+            for x in self.tile.x0..self.tile.x1 {
+                let index = (y - self.tile.y0) * tile_width + x - self.tile.x0;
+                box_sums[y * image_width + x] = self.box_sums[index];
+                sample_counts[y * image_width + x] = self.sample_counts[index];
             }
-            write_color(out, &color, samplers_per_pixel).unwrap();
-            pb.inc();
         }
     }
-    pb.finish();
 }
 
-fn write_color<W: Write>(out: &mut W, color: &Color, samplers_per_pixel: usize) -> io::Result<()> {
-    let samplers_factor = 1.0 / (samplers_per_pixel as f64);
+// Renders the samples of every pixel in the tile into a film
+fn render_tile(scene: &Scene, settings: &RenderSettings, sampler: &dyn Sampler, tile: &Tile) -> TileFilm {
+    let width_factor: f64 = 1.0 / (settings.image_width as f64 - 1.0);
+    let height_factor: f64 = 1.0 / (settings.image_height as f64 - 1.0);
+    // With adaptive sampling the first samples are stratified over a grid of the pixel, so
+    // an edge crossing the pixel shows in the variance before the pixel can stop. The other
+    // samplers already stratify the pixel.
+    let strata = match settings.adaptive {
+        Some(adaptive) if settings.sampler == SamplerKind::Independent => (adaptive.min_samples as f64).sqrt() as usize,
+        _ => 0,
+    };
+    let mut film = TileFilm::new(tile, &settings.filter, settings.image_width, settings.image_height);
+    let tile_width = tile.x1 - tile.x0;
+    for j in tile.y0..tile.y1 {
+        for i in tile.x0..tile.x1 {
+            let mut rng = pixel_rng(settings, i, j);
+            let mut estimate = PixelEstimate::default();
+            while estimate.count < settings.samplers_per_pixel {
+                let sample = PixelSample::new(sampler, i, j, estimate.count);
+                let (du, dv) = (sample.get(sampler::PIXEL_DIMENSION), sample.get(sampler::PIXEL_DIMENSION + 1));
+                let (du, dv) = if estimate.count < strata * strata {
+                    let (x, y) = ((estimate.count % strata) as f64, (estimate.count / strata) as f64);
+                    ((x + du) / strata as f64, (y + dv) / strata as f64)
+                } else {
+                    (du, dv)
+                };
+                let u = (i as f64 + du) * width_factor;
+                let v = (j as f64 + dv) * height_factor;
+                let mut camera_samples = sample.stream(sampler::CAMERA_DIMENSION, sampler::CAMERA_DIMENSIONS, &mut rng);
+                let ray = scene.camera.gen_ray(u, v, &mut camera_samples);
+                let radiance = ray_color(&ray, scene, settings.max_depth, 0, 0.0, None, &sample, &mut rng);
+                film.add_sample(&settings.filter, i as f64 + du, j as f64 + dv, &radiance);
+                estimate.add(radiance.luminance());
+                if let Some(ref adaptive) = settings.adaptive {
+                    if estimate.converged(adaptive) {
+                        break;
+                    }
+                }
+            }
+            film.sample_counts[(j - tile.y0) * tile_width + i - tile.x0] = estimate.count;
+        }
+    }
+    film
+}
 
-    let r = (255.999 * clamp((color.x() * samplers_factor).sqrt(), 0.0, 1.0)) as u8;
-    let g = (255.999 * clamp((color.y() * samplers_factor).sqrt(), 0.0, 1.0)) as u8;
-    let b = (255.999 * clamp((color.z() * samplers_factor).sqrt(), 0.0, 1.0)) as u8;
-    out.write_fmt(format_args!("{} {} {}\n", r, g, b))
+// Running mean and variance of a pixel's sample luminances, by Welford's algorithm
+#[derive(Copy, Clone, Debug, Default)]
+struct PixelEstimate {
+    count: usize,
+    mean: f64,
+    // Sum of squared differences from the mean
+    m2: f64,
 }
 
-fn ray_color<T: Hittable>(ray: &Ray, background_color: &Color, hittable: &T, depth: usize) -> Color {
+impl PixelEstimate {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn converged(&self, adaptive: &AdaptiveSampling) -> bool {
+        if self.count < adaptive.min_samples.max(2) {
+            return false;
+        }
+
+        let variance = self.m2 / (self.count - 1) as f64;
+        let standard_error = (variance / self.count as f64).sqrt();
+        standard_error <= adaptive.error_threshold * self.mean.max(MIN_ERROR_LUMINANCE)
+    }
+}
+
+// Every pixel draws from its own generator, seeded from the render seed and the pixel's position,
+// so the image doesn't depend on how tiles are spread over the worker threads. It supplies the
+// random numbers past the dimensions the sampler hands out.
+fn pixel_rng(settings: &RenderSettings, i: usize, j: usize) -> StdRng {
+    let index = (j * settings.image_width + i) as u64;
+    StdRng::seed_from_u64(settings.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ index)
+}
+
+// scatter_pdf is the density the previous bounce sampled the ray direction with, or 0 when
+// the direction was not sampled against the lights (camera rays and specular bounces).
+// medium is the absorption coefficient of the object interior the ray travels through, if any.
+// bounce counts up from 0 at the camera while depth counts down.
+#[allow(clippy::too_many_arguments)]
+fn ray_color(ray: &Ray, scene: &Scene, depth: usize, bounce: usize, scatter_pdf: f64, medium: Option<Color>,
+                sample: &PixelSample, rng: &mut dyn RngCore) -> Color {
     if depth == 0 {
         return Color::default();
     }
 
-    if let Some(r) = hittable.hit(ray, 0.0001, std::f64::MAX) {
-        let emit = r.material.emitted(r.u, r.v, &r.p);
-        if let Some((attenuation, ray)) = r.material.scatter(ray, &r) {
-            emit + ray_color(&ray, background_color, hittable, depth - 1) * attenuation
+    let world = scene.world.as_ref();
+    let lights = &scene.lights;
+    if let Some(r) = world.hit(ray, 0.0001, f64::MAX, rng) {
+        // A path reaching a dispersive material continues at a single wavelength, weighted so
+        // that its color averages out to the RGB result where nothing disperses
+        let (ray, wavelength_weight) = if r.material.dispersive() && ray.wavelength().is_none() {
+            let wavelength = spectrum::sample_wavelength(sample.get(sampler::WAVELENGTH_DIMENSION));
+            (ray.with_wavelength(Some(wavelength)), Some(spectrum::wavelength_weight(wavelength)))
         } else {
-            emit
+            (*ray, None)
+        };
+        let ray = &ray;
+
+        let mut emit = r.material.emitted(r.u, r.v, &r.p);
+        if scatter_pdf > 0.0 && !lights.is_empty() {
+            // Lights reached by the material's own sampling share the estimate with light sampling
+            let light_pdf = lights.pdf_value(&ray.origin(), &ray.dir());
+            emit *= power_heuristic(scatter_pdf, light_pdf);
+        }
+
+        let dimension = sampler::FIRST_BOUNCE_DIMENSION + bounce * sampler::BOUNCE_DIMENSIONS;
+        let scattered = r.material.sample(ray, &r, &mut sample.stream(dimension, sampler::SCATTER_DIMENSIONS, rng));
+        let color = match scattered {
+            Some(srec) if srec.specular => {
+                let next_medium = medium_along(&r, &srec.ray.dir(), medium);
+                let next_ray = srec.ray.with_wavelength(ray.wavelength());
+                emit + ray_color(&next_ray, scene, depth - 1, bounce + 1, 0.0, next_medium, sample, rng) * srec.attenuation
+            },
+            Some(srec) => {
+                let direct = if lights.is_empty() {
+                    Color::default()
+                } else {
+                    let mut light_samples = sample.stream(dimension + sampler::SCATTER_DIMENSIONS, sampler::LIGHT_DIMENSIONS, rng);
+                    let dir = lights.random(&r.p, &mut light_samples).unit_vector();
+                    sample_lights(ray, &r, &dir, medium_along(&r, &dir, medium), scene, rng)
+                };
+                let next_medium = medium_along(&r, &srec.ray.dir(), medium);
+                let next_ray = srec.ray.with_wavelength(ray.wavelength());
+                emit + direct + ray_color(&next_ray, scene, depth - 1, bounce + 1, srec.pdf, next_medium, sample, rng) * srec.attenuation
+            },
+            None => emit,
+        };
+        let color = match wavelength_weight {
+            Some(weight) => color * weight,
+            None => color,
+        };
+        match medium {
+            Some(absorption) => color * transmittance(&absorption, r.t * ray.dir().length()),
+            None => color,
         }
     } else {
-        *background_color
+        let color = scene.environment.value(&ray.dir());
+        match medium {
+            // Escaping from inside an open object, like the underside of a plane
+            Some(absorption) => color * transmittance(&absorption, f64::INFINITY),
+            None => color,
+        }
     }
 }
 
-fn clamp(v: f64, min: f64, max: f64) -> f64 {
-    if v < min {
-        min
-    } else if v > max {
-        max
-    } else {
-        v
+// Next event estimation, light arriving at the hit point along dir, a direction sampled from the lights
+fn sample_lights(ray: &Ray, rec: &HitRecord, dir: &Vec3, medium: Option<Color>, scene: &Scene,
+                 rng: &mut dyn RngCore) -> Color {
+    let light_pdf = scene.lights.pdf_value(&rec.p, dir);
+    if light_pdf <= 0.0 {
+        return Color::default();
+    }
+
+    let scatter_pdf = rec.material.pdf(ray, rec, dir);
+    if scatter_pdf <= 0.0 {
+        return Color::default();
+    }
+
+    let light_ray = Ray::new(&rec.p, dir, ray.t());
+    match scene.world.hit(&light_ray, 0.0001, f64::MAX, rng) {
+        Some(r) => {
+            let mut emit = r.material.emitted(r.u, r.v, &r.p);
+            if let Some(absorption) = medium {
+                emit = emit * transmittance(&absorption, r.t);
+            }
+            let f = rec.material.eval(ray, rec, dir);
+            emit * f * (power_heuristic(light_pdf, scatter_pdf) / light_pdf)
+        },
+        None => Color::default(),
+    }
+}
+
+// The medium a ray leaving the hit point along dir travels through. Passing into an absorbing
+// object enters its interior and passing out of one leaves for empty space, media don't nest.
+fn medium_along(rec: &HitRecord, dir: &Vec3, medium: Option<Color>) -> Option<Color> {
+    match rec.material.absorption() {
+        Some(absorption) if dir.dot(rec.normal) < 0.0 => if rec.front_face {Some(absorption)} else {None},
+        _ => medium,
     }
-}
\ No newline at end of file
+}
+
+// Fraction of light left after travelling distance, which may be infinite, through a medium
+// with the absorption coefficient
+fn transmittance(absorption: &Color, distance: f64) -> Color {
+    let channel = |a: f64| if a > 0.0 {(-a * distance).exp()} else {1.0};
+    Color::new(channel(absorption.x()), channel(absorption.y()), channel(absorption.z()))
+}
+
+// Multiple importance sampling weight of a sample drawn with density pdf, against the other strategy
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf_2 = pdf * pdf;
+    pdf_2 / (pdf_2 + other_pdf * other_pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use bvh::BVH;
+    use camera::Camera;
+    use environment::GradientSky;
+    use filter::FilterKind;
+    use material::{Lambertian, Metal, Dielectric, Dispersion, DiffuseLight};
+    use rect::AARect;
+    use sphere::{Sphere, AnimatedSphere};
+    use texture::{SolidTexture, NoiseTexture, NoiseKind};
+    use vec3::Point3;
+
+    // Diffuse, glossy, dispersive and moving objects under a sky and an area light, so that every
+    // path that draws random numbers is exercised
+    fn scene() -> Scene {
+        let solid = |r, g, b| Arc::new(SolidTexture::new(&Color::new(r, g, b)));
+        let noise = Arc::new(NoiseTexture::new(NoiseKind::Marble, &Color::new(0.8, 0.8, 0.8), 4.0, 7, 1));
+        let light: Arc<dyn Hittable> = Arc::new(AARect::new_xz(-1.0, 1.0, -1.0, 1.0, 3.0,
+                                                               Arc::new(DiffuseLight::new(solid(4.0, 4.0, 4.0)))));
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::new(noise)))),
+            Arc::new(Sphere::new(&Point3::new(-1.1, 0.5, 0.0), 0.5, Arc::new(Metal::new(solid(0.8, 0.6, 0.2), 0.3)))),
+            Arc::new(Sphere::new(&Point3::new(0.0, 0.5, 0.0), 0.5,
+                                 Arc::new(Dielectric::new(1.5).dispersing(Dispersion::bk7())))),
+            Arc::new(AnimatedSphere::new(&Point3::new(1.1, 0.5, 0.0), &Point3::new(1.1, 0.7, 0.0), 1.0, 0.5,
+                                         Arc::new(Lambertian::new(solid(0.2, 0.3, 0.7))))),
+            light.clone(),
+        ];
+        let camera = Camera::new(&Point3::new(0.0, 1.5, 5.0), &Point3::new(0.0, 0.5, 0.0), &Vec3::new(0.0, 1.0, 0.0),
+                                 40.0, 1.0, 0.1, 5.0, 1.0);
+        let sky = GradientSky::new(&Color::new(1.0, 1.0, 1.0), &Color::new(0.5, 0.7, 1.0), &Vec3::default(), 0.5);
+        let mut scene = Scene::with_environment(Arc::new(BVH::new(objects)), camera, Arc::new(sky));
+        scene.lights.add(light);
+        scene
+    }
+
+    fn assert_same_image_with_threads(sampler: SamplerKind, filter: FilterKind, adaptive: Option<AdaptiveSampling>) {
+        let scene = scene();
+        let settings = |threads| RenderSettings {
+            // Several tiles, whose filter footprints overlap their neighbours
+            image_width: 40,
+            image_height: 40,
+            samplers_per_pixel: 16,
+            max_depth: 8,
+            threads,
+            seed: 5,
+            adaptive, sampler,
+            filter: Filter::with_default_radius(filter),
+        };
+
+        let (single, single_counts) = render_with_sample_counts(&scene, &settings(1));
+        let (multi, multi_counts) = render_with_sample_counts(&scene, &settings(4));
+        assert!(single.pixels().iter().any(|c| !c.near_zero()), "the scene rendered black");
+        if adaptive.is_some() {
+            assert!(single_counts.iter().any(|&count| count < 16), "no pixel stopped sampling early");
+        }
+        assert_eq!(single_counts, multi_counts);
+        let bits = |image: &Framebuffer| -> Vec<[u64; 3]> {
+            image.pixels().iter().map(|c| [c.x().to_bits(), c.y().to_bits(), c.z().to_bits()]).collect()
+        };
+        assert!(bits(&single) == bits(&multi), "{:?} with {:?} differs between 1 and 4 threads", sampler, filter);
+    }
+
+    #[test]
+    fn sobol_mitchell_is_independent_of_thread_count() {
+        assert_same_image_with_threads(SamplerKind::Sobol, FilterKind::Mitchell, None);
+    }
+
+    #[test]
+    fn adaptive_halton_gaussian_is_independent_of_thread_count() {
+        let adaptive = AdaptiveSampling {min_samples: 4, error_threshold: 0.05};
+        assert_same_image_with_threads(SamplerKind::Halton, FilterKind::Gaussian, Some(adaptive));
+    }
+
+    #[test]
+    fn adaptive_stratified_lanczos_is_independent_of_thread_count() {
+        let adaptive = AdaptiveSampling {min_samples: 4, error_threshold: 0.05};
+        assert_same_image_with_threads(SamplerKind::Stratified, FilterKind::Lanczos, Some(adaptive));
+    }
+
+    // Develops an image from the samples returned for every pixel (i, j), as offsets within the
+    // pixel and colors, with row 0 at the bottom like the renderer's tiles
+    fn develop_samples<F>(filter: &Filter, width: usize, height: usize, samples: F) -> Framebuffer
+            where F: Fn(usize, usize) -> Vec<(f64, f64, Color)> {
+        let films = make_tiles(width, height).iter().map(|tile| {
+            let mut film = TileFilm::new(tile, filter, width, height);
+            for j in tile.y0..tile.y1 {
+                for i in tile.x0..tile.x1 {
+                    let pixel_samples = samples(i, j);
+                    for (du, dv, color) in pixel_samples.iter() {
+                        film.add_sample(filter, i as f64 + du, j as f64 + dv, color);
+                    }
+                    film.sample_counts[(j - tile.y0) * (tile.x1 - tile.x0) + i - tile.x0] = pixel_samples.len();
+                }
+            }
+            film
+        }).collect::<Vec<_>>();
+        develop(films, width, height).0
+    }
+
+    fn grid_samples(n: usize, color: &Color) -> Vec<(f64, f64, Color)> {
+        (0..n * n).map(|k| (((k % n) as f64 + 0.5) / n as f64, ((k / n) as f64 + 0.5) / n as f64, *color)).collect()
+    }
+
+    const FILTER_KINDS: [FilterKind; 5] = [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos];
+
+    #[test]
+    fn filtered_film_keeps_a_constant_color() {
+        let color = Color::new(0.2, 0.5, 0.9);
+        for kind in FILTER_KINDS.iter() {
+            // Tiles of several sizes, whose filter margins overlap and are clipped at the edges
+            for filter in [Filter::with_default_radius(*kind), Filter::new(*kind, 3.0)].iter() {
+                let image = develop_samples(filter, 40, 24, |_, _| grid_samples(4, &color));
+                for pixel in image.pixels() {
+                    assert!((*pixel - color).length() < 1e-9, "{:?} turned {:?} into {:?}", filter, color, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_keeps_every_pixel_to_its_own_samples() {
+        let color = |i: usize, j: usize| Color::new(i as f64, j as f64, (i * j % 7) as f64);
+        let image = develop_samples(&Filter::default(), 20, 18, |i, j| grid_samples(3, &color(i, j)));
+        for j in 0..18 {
+            for i in 0..20 {
+                assert!((image.get(i, 17 - j) - color(i, j)).length() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_lobes_fall_back_to_the_box_filtered_mean() {
+        // One sample at the center of pixel (5, 5), and many from its right neighbour where the
+        // Mitchell and Lanczos filters of radius 2 are negative, 1.4 pixels away
+        let red = Color::new(1.0, 0.0, 0.0);
+        let green = Color::new(0.0, 1.0, 0.0);
+        for kind in [FilterKind::Mitchell, FilterKind::Lanczos].iter() {
+            let filter = Filter::new(*kind, 2.0);
+            assert!(filter.evaluate(1.4, 0.0) < 0.0);
+            let image = develop_samples(&filter, 11, 11, |i, j| match (i, j) {
+                (5, 5) => vec![(0.5, 0.5, red)],
+                (6, 5) => vec![(0.9, 0.5, green); 100],
+                _ => Vec::new(),
+            });
+            assert!((image.get(5, 5) - red).length() < 1e-12, "{:?} made the pixel {:?}", kind, image.get(5, 5));
+            assert!(image.pixels().iter().all(|c| c.x().is_finite() && c.y().is_finite() && c.z().is_finite()));
+        }
+    }
+}
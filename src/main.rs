@@ -1,13 +1,24 @@
-use std::fs::File;
-use std::rc::Rc;
-use rand::{self,Rng};
+use std::sync::Arc;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use clap::{Arg, App};
+use raytracer::{RenderSettings, AdaptiveSampling};
+use raytracer::output::OutputFormat;
+use raytracer::sampler::SamplerKind;
+use raytracer::filter::{Filter, FilterKind};
+use raytracer::tonemap::{ToneMapOperator, ToneMapper};
+use raytracer::environment::{GradientSky, ImageEnvironment};
+use raytracer::scene::Scene;
+use raytracer::scene_file::SceneFile;
+use raytracer::obj::load_obj;
+use raytracer::transform::{RotateY, Translate};
 use raytracer::vec3::{Point3, Color, Vec3};
-use raytracer::hittable::{Hittable, BVH};
+use raytracer::hittable::{Hittable, HittableList};
+use raytracer::bvh::BVH;
 use raytracer::sphere::{Sphere, AnimatedSphere};
 use raytracer::camera::Camera;
 use raytracer::material::{Lambertian, Metal, Dielectric, DiffuseLight};
-use raytracer::texture::{SolidTexture, CheckerTexture, ImageTexture};
+use raytracer::texture::{SolidTexture, CheckerTexture, ImageTexture, NoiseTexture, NoiseKind};
 use raytracer::rect::AARect;
 use raytracer::aabox::AABox;
 use raytracer::medium::ConstantMedium;
@@ -20,6 +31,13 @@ fn main() {
         }
     };
 
+    let is_float = |v: String| {
+        match v.parse::<f64>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(String::from("The value cannot convert to f64"))
+        }
+    };
+
     let matches = App::new("My Super Program")
                             .version("0.1.0")
                             .author("VincentGong. <return0xffff@gmail.com>")
@@ -30,7 +48,7 @@ fn main() {
                                 .value_name("WIDTH")
                                 .help("Sets output file width")
                                 .takes_value(true)
-                                .required(true)
+                                .required_unless("SCENE_FILE")
                                 .validator(is_number))
                             .arg(Arg::with_name("HEIGHT")
                                 .short("h")
@@ -38,7 +56,7 @@ fn main() {
                                 .value_name("HEIGHT")
                                 .help("Sets output file height")
                                 .takes_value(true)
-                                .required(true)
+                                .required_unless("SCENE_FILE")
                                 .validator(is_number))
                             .arg(Arg::with_name("SAMPLERS")
                                 .short("s")
@@ -47,6 +65,44 @@ fn main() {
                                 .help("Sets samplers per pixel, default is 500")
                                 .takes_value(true)
                                 .validator(is_number))
+                            .arg(Arg::with_name("ADAPTIVE_THRESHOLD")
+                                .long("adaptive-threshold")
+                                .value_name("ERROR")
+                                .help("Enables adaptive sampling, pixels stop once the relative standard error of their luminance is below ERROR, e.g. 0.01, and take at most --samplers samples")
+                                .takes_value(true)
+                                .validator(is_float))
+                            .arg(Arg::with_name("MIN_SAMPLES")
+                                .long("min-samples")
+                                .value_name("MIN_SAMPLES")
+                                .help("Sets the samples every pixel takes before adaptive sampling may stop it, default is 16")
+                                .takes_value(true)
+                                .validator(is_number))
+                            .arg(Arg::with_name("SAMPLE_HEATMAP")
+                                .long("sample-heatmap")
+                                .value_name("SAMPLE_HEATMAP")
+                                .help("Also saves an image of the samples every pixel took, black for none to white for --samplers")
+                                .takes_value(true))
+                            .arg(Arg::with_name("SAMPLER")
+                                .long("sampler")
+                                .value_name("SAMPLER")
+                                .help("Sample generator (independent | stratified | halton | sobol), default is independent")
+                                .takes_value(true)
+                                .validator(|v| v.parse::<SamplerKind>().map(|_| ())))
+                            .arg(Arg::with_name("FILTER")
+                                .long("filter")
+                                .value_name("FILTER")
+                                .help("Pixel reconstruction filter (box | tent | gaussian | mitchell | lanczos), default is box")
+                                .takes_value(true)
+                                .validator(|v| v.parse::<FilterKind>().map(|_| ())))
+                            .arg(Arg::with_name("FILTER_RADIUS")
+                                .long("filter-radius")
+                                .value_name("PIXELS")
+                                .help("Sets the filter radius in pixels, default is 0.5 for box, 1 for tent, 1.5 for gaussian and 2 for mitchell and lanczos")
+                                .takes_value(true)
+                                .validator(|v| match v.parse::<f64>() {
+                                    Ok(radius) if radius > 0.0 => Ok(()),
+                                    _ => Err(String::from("The value must be a positive number")),
+                                }))
                             .arg(Arg::with_name("DEPTH")
                                 .short("d")
                                 .long("depth")
@@ -54,11 +110,82 @@ fn main() {
                                 .help("Sets max ray trace depth, default is 50")
                                 .takes_value(true)
                                 .validator(is_number))
+                            .arg(Arg::with_name("THREADS")
+                                .short("t")
+                                .long("threads")
+                                .value_name("THREADS")
+                                .help("Sets number of render threads, default is one per CPU core")
+                                .takes_value(true)
+                                .validator(is_number))
+                            .arg(Arg::with_name("SEED")
+                                .long("seed")
+                                .value_name("SEED")
+                                .help("Seeds the scene generation and rendering, the same seed renders the same image, default is 0")
+                                .takes_value(true)
+                                .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| String::from("The value cannot convert to u64"))))
                             .arg(Arg::with_name("SCENE")
                                 .long("scene")
                                 .value_name("SCENE")
-                                .help("Scene to render (earch | random | light | cornell_box | final), default is random")
+                                .help("Scene to render (earch | random | light | cornell_box | final | perlin), default is random")
+                                .takes_value(true))
+                            .arg(Arg::with_name("SCENE_FILE")
+                                .long("scene-file")
+                                .value_name("SCENE_FILE")
+                                .help("Renders the scene described by a TOML file instead of a built in scene, its render settings apply unless given on the command line")
+                                .takes_value(true)
+                                .conflicts_with("SCENE"))
+                            .arg(Arg::with_name("OBJ")
+                                .long("obj")
+                                .value_name("OBJ")
+                                .help("Adds the meshes of a Wavefront OBJ file to the scene")
                                 .takes_value(true))
+                            .arg(Arg::with_name("ENVIRONMENT_MAP")
+                                .long("environment-map")
+                                .value_name("ENVIRONMENT_MAP")
+                                .help("Lights the scene with an equirectangular environment map (.hdr or any LDR image), replacing the scene background")
+                                .takes_value(true))
+                            .arg(Arg::with_name("ENVIRONMENT_ROTATION")
+                                .long("environment-rotation")
+                                .value_name("DEGREES")
+                                .help("Rotates the environment map around the vertical axis, default is 0")
+                                .takes_value(true)
+                                .allow_hyphen_values(true)
+                                .validator(is_float))
+                            .arg(Arg::with_name("ENVIRONMENT_INTENSITY")
+                                .long("environment-intensity")
+                                .value_name("INTENSITY")
+                                .help("Scales the environment map radiance, default is 1.0")
+                                .takes_value(true)
+                                .validator(is_float))
+                            .arg(Arg::with_name("FORMAT")
+                                .short("f")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .help("Output file format (png | jpeg | bmp | ppm | ppm-ascii | hdr | pfm | exr), default is chosen by the output file extension")
+                                .takes_value(true)
+                                .validator(|v| v.parse::<OutputFormat>().map(|_| ())))
+                            .arg(Arg::with_name("TONEMAP")
+                                .long("tonemap")
+                                .value_name("TONEMAP")
+                                .help("Tone mapping operator for low dynamic range output (clamp | reinhard | reinhard-extended | hable | aces), default is clamp")
+                                .takes_value(true)
+                                .validator(|v| v.parse::<ToneMapOperator>().map(|_| ())))
+                            .arg(Arg::with_name("WHITE_POINT")
+                                .long("white-point")
+                                .value_name("WHITE_POINT")
+                                .help("Sets the white point of the reinhard-extended operator, default is 4.0")
+                                .takes_value(true)
+                                .validator(|v| match v.parse::<f64>() {
+                                    Ok(white) if white > 0.0 => Ok(()),
+                                    _ => Err(String::from("The value must be a positive number")),
+                                }))
+                            .arg(Arg::with_name("EXPOSURE")
+                                .long("exposure")
+                                .value_name("EXPOSURE")
+                                .help("Sets exposure adjustment in stops before tone mapping, default is 0")
+                                .takes_value(true)
+                                .allow_hyphen_values(true)
+                                .validator(is_float))
                             .arg(Arg::with_name("OUTPUT")
                                 .help("Sets the output file to use")
                                 .required(true)
@@ -67,41 +194,121 @@ fn main() {
 
     // Image
     let output = matches.value_of("OUTPUT").unwrap();
+    let format = match matches.value_of("FORMAT") {
+        Some(format) => format.parse::<OutputFormat>().unwrap(),
+        None => OutputFormat::from_path(output),
+    };
+
+    let scene_file = matches.value_of("SCENE_FILE").map(|path| {
+        SceneFile::load(path).unwrap_or_else(|e| panic!("Failed to load scene file {}", e))
+    });
+    let file_options = scene_file.as_ref().map(|f| f.render_options().clone()).unwrap_or_default();
+    let defaults = RenderSettings::default();
 
-    let image_width = matches.value_of("WIDTH").unwrap().parse::<usize>().unwrap();
-    let image_height = matches.value_of("HEIGHT").unwrap().parse::<usize>().unwrap();
+    let image_width = matches.value_of("WIDTH").map(|v| v.parse::<usize>().unwrap())
+        .or(file_options.width).unwrap_or(defaults.image_width);
+    let image_height = matches.value_of("HEIGHT").map(|v| v.parse::<usize>().unwrap())
+        .or(file_options.height).unwrap_or(defaults.image_height);
     let aspect_ratio = image_width as f64 / image_height as f64;
 
-    let samplers_per_pixel = matches.value_of("SAMPLERS").unwrap_or("500").parse::<usize>().unwrap();
-    let max_depth = matches.value_of("DEPTH").unwrap_or("50").parse::<usize>().unwrap();
+    let samplers_per_pixel = matches.value_of("SAMPLERS").map(|v| v.parse::<usize>().unwrap())
+        .or(file_options.samples).unwrap_or(defaults.samplers_per_pixel);
+    let max_depth = matches.value_of("DEPTH").map(|v| v.parse::<usize>().unwrap())
+        .or(file_options.max_depth).unwrap_or(defaults.max_depth);
+    let threads = matches.value_of("THREADS").unwrap_or("0").parse::<usize>().unwrap();
+    let seed = matches.value_of("SEED").map(|v| v.parse::<u64>().unwrap())
+        .or(file_options.seed).unwrap_or(defaults.seed);
+    let sampler = matches.value_of("SAMPLER").map(|v| v.parse::<SamplerKind>().unwrap())
+        .or(file_options.sampler).unwrap_or(defaults.sampler);
+    let filter_kind = matches.value_of("FILTER").map(|v| v.parse::<FilterKind>().unwrap())
+        .or(file_options.filter).unwrap_or(defaults.filter.kind());
+    let filter = match matches.value_of("FILTER_RADIUS").map(|v| v.parse::<f64>().unwrap()).or(file_options.filter_radius) {
+        Some(radius) => Filter::new(filter_kind, radius),
+        None => Filter::with_default_radius(filter_kind),
+    };
+    let adaptive = matches.value_of("ADAPTIVE_THRESHOLD").map(|v| v.parse::<f64>().unwrap())
+        .or(file_options.adaptive_threshold)
+        .map(|error_threshold| AdaptiveSampling {
+            min_samples: matches.value_of("MIN_SAMPLES").map(|v| v.parse::<usize>().unwrap())
+                .or(file_options.min_samples).unwrap_or(AdaptiveSampling::default().min_samples),
+            error_threshold,
+        });
+
+    let mut tone_map_operator = matches.value_of("TONEMAP").unwrap_or("clamp").parse::<ToneMapOperator>().unwrap();
+    if let ToneMapOperator::ReinhardExtended {ref mut white} = tone_map_operator {
+        *white = matches.value_of("WHITE_POINT").unwrap_or("4.0").parse::<f64>().unwrap();
+    }
+    let exposure = matches.value_of("EXPOSURE").unwrap_or("0").parse::<f64>().unwrap();
+    let tone_mapper = ToneMapper::new(tone_map_operator, exposure);
 
     let scene = matches.value_of("SCENE").unwrap_or("random");
     
     // World and Camera
-    let mut rng = rand::thread_rng();
-    let (world, camera) = match scene {
-        "earch" => earch_scene(&mut rng, aspect_ratio),
-        "light" => light_scene(&mut rng, aspect_ratio),
-        "cornell_box" => cornell_box_scene(&mut rng, aspect_ratio),
-        "final" => final_scene(&mut rng, aspect_ratio),
-        _ => random_scene(&mut rng, aspect_ratio),
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut scene = match scene_file {
+        Some(ref file) => file.build(aspect_ratio).unwrap_or_else(|e| panic!("Failed to build scene {}", e)),
+        None => match scene {
+            "earch" => earch_scene(&mut rng, aspect_ratio),
+            "light" => light_scene(&mut rng, aspect_ratio),
+            "cornell_box" => cornell_box_scene(&mut rng, aspect_ratio),
+            "final" => final_scene(&mut rng, aspect_ratio),
+            "perlin" => perlin_scene(&mut rng, aspect_ratio),
+            _ => random_scene(&mut rng, aspect_ratio),
+        },
     };
 
     // Render
-    let mut out = File::create(output).unwrap();
-    raytracer::render(&world, &camera, &mut out, 
-                    image_width, image_height,
-                    samplers_per_pixel, max_depth);
+    let settings = RenderSettings {
+        image_width, image_height,
+        samplers_per_pixel, max_depth,
+        threads, seed,
+        adaptive, sampler, filter,
+    };
+    if let Some(path) = matches.value_of("OBJ") {
+        let default_material = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)))));
+        let meshes = load_obj(path, default_material, false)
+            .unwrap_or_else(|e| panic!("Failed to load OBJ file {}", e));
+        let mut world = HittableList::new(scene.world.clone());
+        for (mesh, emissive) in meshes {
+            let mesh: Arc<dyn Hittable> = Arc::new(mesh);
+            // Meshes with an emissive MTL material light the scene through light sampling too
+            if emissive {
+                scene.lights.add(mesh.clone());
+            }
+            world.add(mesh);
+        }
+        scene.world = Arc::new(world);
+    }
+
+    if let Some(path) = matches.value_of("ENVIRONMENT_MAP") {
+        let rotation = matches.value_of("ENVIRONMENT_ROTATION").unwrap_or("0").parse::<f64>().unwrap();
+        let intensity = matches.value_of("ENVIRONMENT_INTENSITY").unwrap_or("1").parse::<f64>().unwrap();
+        let environment = ImageEnvironment::new(path, &Vec3::new(0.0, rotation, 0.0), intensity)
+            .unwrap_or_else(|e| panic!("Failed to load environment map {}: {}", path, e));
+        scene.environment = Arc::new(environment);
+    }
+
+    let (image, sample_counts) = raytracer::render_with_sample_counts(&scene, &settings);
+    if let Some(path) = matches.value_of("SAMPLE_HEATMAP") {
+        let heatmap = raytracer::heatmap::sample_heatmap(&sample_counts, image_width, image_height, samplers_per_pixel);
+        raytracer::output::save(&heatmap, path, OutputFormat::from_path(path)).unwrap();
+    }
+    let image = if format.is_hdr() {
+        image
+    } else {
+        tone_mapper.apply(&image)
+    };
+    raytracer::output::save(&image, output, format).unwrap();
 }
 
-fn random_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
+fn random_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> Scene {
     // World
-    let mut world = Vec::<Rc<dyn Hittable>>::new();
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
 
-    let odd = Rc::new(SolidTexture::new(&Color::new(0.2, 0.3, 0.1)));
-    let even = Rc::new(SolidTexture::new(&Color::new(0.9, 0.9, 0.9)));
-    let ground_material = Rc::new(Lambertian::new(Rc::new(CheckerTexture::new(odd, even))));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material)));
+    let odd = Arc::new(SolidTexture::new(&Color::new(0.2, 0.3, 0.1)));
+    let even = Arc::new(SolidTexture::new(&Color::new(0.9, 0.9, 0.9)));
+    let ground_material = Arc::new(Lambertian::new(Arc::new(CheckerTexture::new(odd, even))));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material)));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -115,32 +322,32 @@ fn random_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
             let choose_mat = rng.gen_range(0.0..1.0);
             if choose_mat < 0.8 {
                 // diffuse
-                let albedo = Color::random() * Color::random();
-                let sphere_material = Rc::new(Lambertian::new(Rc::new(SolidTexture::new(&albedo))));
+                let albedo = Color::random(rng) * Color::random(rng);
+                let sphere_material = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&albedo))));
                 let center1 = center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0);
-                world.push(Rc::new(AnimatedSphere::new(&center, &center1, 1.0, 0.2, sphere_material)));
+                world.push(Arc::new(AnimatedSphere::new(&center, &center1, 1.0, 0.2, sphere_material)));
             } else if choose_mat < 0.95 {
                 // metal
-                let albedo = Color::random_in(0.5, 1.0);
+                let albedo = Color::random_in(0.5, 1.0, rng);
                 let fuzz = rng.gen_range(0.0..0.5);
-                let sphere_material = Rc::new(Metal::new(Rc::new(SolidTexture::new(&albedo)), fuzz));
-                world.push(Rc::new(Sphere::new(&center, 0.2, sphere_material)));
+                let sphere_material = Arc::new(Metal::new(Arc::new(SolidTexture::new(&albedo)), fuzz));
+                world.push(Arc::new(Sphere::new(&center, 0.2, sphere_material)));
             } else {
                 // glass
-                let sphere_material = Rc::new(Dielectric::new(1.5));
-                world.push(Rc::new(Sphere::new(&center, 0.2, sphere_material)));
+                let sphere_material = Arc::new(Dielectric::new(1.5));
+                world.push(Arc::new(Sphere::new(&center, 0.2, sphere_material)));
             }
         }
     }
 
-    let material = Rc::new(Dielectric::new(1.5));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, 1.0, 0.0), 1.0, material)));
+    let material = Arc::new(Dielectric::new(1.5));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, 1.0, 0.0), 1.0, material)));
 
-    let material = Rc::new(Lambertian::new(Rc::new(SolidTexture::new(&Color::new(0.4, 0.2, 0.1)))));
-    world.push(Rc::new(Sphere::new(&Point3::new(-4.0, 1.0, 0.0), 1.0, material)));
+    let material = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.4, 0.2, 0.1)))));
+    world.push(Arc::new(Sphere::new(&Point3::new(-4.0, 1.0, 0.0), 1.0, material)));
 
-    let material = Rc::new(Metal::new(Rc::new(SolidTexture::new(&Color::new(0.7, 0.6, 0.5))), 0.0));
-    world.push(Rc::new(Sphere::new(&Point3::new(4.0, 1.0, 0.0), 1.0, material)));
+    let material = Arc::new(Metal::new(Arc::new(SolidTexture::new(&Color::new(0.7, 0.6, 0.5))), 0.0));
+    world.push(Arc::new(Sphere::new(&Point3::new(4.0, 1.0, 0.0), 1.0, material)));
 
     let world = BVH::new(world);
 
@@ -156,20 +363,23 @@ fn random_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
                             &up,
                             20.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
     
-    (world, camera)
+    Scene::with_environment(Arc::new(world), camera, Arc::new(GradientSky::default()))
 }
 
-fn earch_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
+fn earch_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> Scene {
     // World
-    let mut world = Vec::<Rc<dyn Hittable>>::new();
-
-    let texture = Rc::new(ImageTexture::new("assets/earthmap.jpg"));
-    let material = Rc::new(Lambertian::new(texture));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, 0.0, 0.0), 2.0, material)));
-
-    let texture = Rc::new(SolidTexture::new(&Color::new(4.0, 4.0, 4.0)));
-    let material = Rc::new(DiffuseLight::new(texture));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, 0.0, 3.5), 1.0, material)));
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
+    let mut lights = HittableList::default();
+
+    let texture = Arc::new(ImageTexture::new("assets/earthmap.jpg"));
+    let material = Arc::new(Lambertian::new(texture));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, 0.0, 0.0), 2.0, material)));
+
+    let texture = Arc::new(SolidTexture::new(&Color::new(4.0, 4.0, 4.0)));
+    let material = Arc::new(DiffuseLight::new(texture));
+    let light = Arc::new(Sphere::new(&Point3::new(0.0, 0.0, 3.5), 1.0, material));
+    world.push(light.clone());
+    lights.add(light);
     let world = BVH::new(world);
 
     // Camera 
@@ -184,22 +394,58 @@ fn earch_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
                             &up,
                             20.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
 
-    (world, camera)
+    let mut scene = Scene::new(Arc::new(world), camera);
+    scene.lights = lights;
+    scene
 }
 
-fn light_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
-    let mut world = Vec::<Rc<dyn Hittable>>::new();
+fn perlin_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> Scene {
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
+
+    let seed = rng.gen();
+    let white = Color::new(1.0, 1.0, 1.0);
+    let texture = Arc::new(NoiseTexture::new(NoiseKind::Marble, &white, 4.0, 7, seed));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::new(texture)))));
+
+    let kinds = [NoiseKind::Noise, NoiseKind::Turbulence, NoiseKind::Marble, NoiseKind::Fbm];
+    for (i, kind) in kinds.iter().enumerate() {
+        let texture = Arc::new(NoiseTexture::new(*kind, &white, 4.0, 7, seed));
+        let center = Point3::new(0.0, 1.0, -4.5 + 3.0 * i as f64);
+        world.push(Arc::new(Sphere::new(&center, 1.0, Arc::new(Lambertian::new(texture)))));
+    }
+    let world = BVH::new(world);
+
+    // Camera 
+    const DIST_TO_FOCUS: f64 = 10.0;
+    const APERTURE: f64 = 0.0;
+    const SHUTTER_DURATION: f64 = 1.0;
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 1.0, 0.0);
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let camera = Camera::new(&look_from,
+                            &look_at,
+                            &up,
+                            40.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
 
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.0, 1.0, 0.0)));
-    let material = Rc::new(Lambertian::new(texture));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, material)));
-    let texture = Rc::new(SolidTexture::new(&Color::new(1.0, 0.0, 0.0)));
-    let material = Rc::new(Lambertian::new(texture));
-    world.push(Rc::new(Sphere::new(&Point3::new(0.0, 2.0, 0.0), 2.0, material)));
+    Scene::with_environment(Arc::new(world), camera, Arc::new(GradientSky::default()))
+}
 
-    let texture = Rc::new(SolidTexture::new(&Color::new(4.0, 4.0, 4.0)));
-    let material = Rc::new(DiffuseLight::new(texture));
-    world.push(Rc::new(AARect::new_xy(3.0, 5.0, 1.0, 3.0, -2.0, material)));
+fn light_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> Scene {
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
+    let mut lights = HittableList::default();
+
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.0, 1.0, 0.0)));
+    let material = Arc::new(Lambertian::new(texture));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, -1000.0, 0.0), 1000.0, material)));
+    let texture = Arc::new(SolidTexture::new(&Color::new(1.0, 0.0, 0.0)));
+    let material = Arc::new(Lambertian::new(texture));
+    world.push(Arc::new(Sphere::new(&Point3::new(0.0, 2.0, 0.0), 2.0, material)));
+
+    let texture = Arc::new(SolidTexture::new(&Color::new(4.0, 4.0, 4.0)));
+    let material = Arc::new(DiffuseLight::new(texture));
+    let light = Arc::new(AARect::new_xy(3.0, 5.0, 1.0, 3.0, -2.0, material));
+    world.push(light.clone());
+    lights.add(light);
     let world = BVH::new(world);
 
     // Camera 
@@ -214,41 +460,50 @@ fn light_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
                             &up,
                             20.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
 
-    (world, camera)
+    let mut scene = Scene::new(Arc::new(world), camera);
+    scene.lights = lights;
+    scene
 }
 
-fn cornell_box_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
-    let mut world = Vec::<Rc<dyn Hittable>>::new();
+fn cornell_box_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> Scene {
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
+    let mut lights = HittableList::default();
 
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.65, 0.05, 0.05)));
-    let red = Rc::new(Lambertian::new(texture));
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)));
-    let white = Rc::new(Lambertian::new(texture));
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.12, 0.45, 0.15)));
-    let green = Rc::new(Lambertian::new(texture));
-    let texture = Rc::new(SolidTexture::new(&Color::new(15.0, 15.0, 15.0)));
-    let light = Rc::new(DiffuseLight::new(texture));
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.65, 0.05, 0.05)));
+    let red = Arc::new(Lambertian::new(texture));
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)));
+    let white = Arc::new(Lambertian::new(texture));
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.12, 0.45, 0.15)));
+    let green = Arc::new(Lambertian::new(texture));
+    let texture = Arc::new(SolidTexture::new(&Color::new(15.0, 15.0, 15.0)));
+    let light = Arc::new(DiffuseLight::new(texture));
 
     // Walls
-    world.push(Rc::new(AARect::new_yz(0.0, 555.0, 0.0, 555.0, 555.0, green.clone())));
-    world.push(Rc::new(AARect::new_yz(0.0, 555.0, 0.0, 555.0, 0.0, red.clone())));
-    world.push(Rc::new(AARect::new_xz(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())));
-    world.push(Rc::new(AARect::new_xz(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
-    world.push(Rc::new(AARect::new_xy(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
+    world.push(Arc::new(AARect::new_yz(0.0, 555.0, 0.0, 555.0, 555.0, green.clone())));
+    world.push(Arc::new(AARect::new_yz(0.0, 555.0, 0.0, 555.0, 0.0, red.clone())));
+    world.push(Arc::new(AARect::new_xz(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())));
+    world.push(Arc::new(AARect::new_xz(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
+    world.push(Arc::new(AARect::new_xy(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
 
     // Light
-    world.push(Rc::new(AARect::new_xz(213.0, 343.0, 227.0, 332.0, 554.0, light)));
+    let light = Arc::new(AARect::new_xz(213.0, 343.0, 227.0, 332.0, 554.0, light));
+    world.push(light.clone());
+    lights.add(light);
 
     // Blocks
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.0, 0.0, 0.0)));
-    let total_black = Rc::new(Lambertian::new(texture));
-    let texture = Rc::new(SolidTexture::new(&Color::new(1.0, 1.0, 1.0)));
-    let total_white = Rc::new(DiffuseLight::new(texture));
-
-    let box1 = Rc::new(AABox::new(&Point3::new(130.0, 0.0, 65.0), &Point3::new(295.0, 165.0, 230.0), white.clone()));
-    let box2 = Rc::new(AABox::new(&Point3::new(265.0, 0.0, 295.0), &Point3::new(430.0, 330.0, 460.0), white.clone()));
-    world.push(Rc::new(ConstantMedium::new(box1, total_black, 0.01)));
-    world.push(Rc::new(ConstantMedium::new(box2, total_white, 0.01)));
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.0, 0.0, 0.0)));
+    let total_black = Arc::new(Lambertian::new(texture));
+    let texture = Arc::new(SolidTexture::new(&Color::new(1.0, 1.0, 1.0)));
+    let total_white = Arc::new(DiffuseLight::new(texture));
+
+    let box1 = Arc::new(AABox::new(&Point3::new(0.0, 0.0, 0.0), &Point3::new(165.0, 165.0, 165.0), white.clone()));
+    let box1 = Arc::new(RotateY::new(box1, -18.0));
+    let box1 = Arc::new(Translate::new(box1, &Vec3::new(130.0, 0.0, 65.0)));
+    let box2 = Arc::new(AABox::new(&Point3::new(0.0, 0.0, 0.0), &Point3::new(165.0, 330.0, 165.0), white.clone()));
+    let box2 = Arc::new(RotateY::new(box2, 15.0));
+    let box2 = Arc::new(Translate::new(box2, &Vec3::new(265.0, 0.0, 295.0)));
+    world.push(Arc::new(ConstantMedium::new(box1, total_black, 0.01)));
+    world.push(Arc::new(ConstantMedium::new(box2, total_white, 0.01)));
 
     let world = BVH::new(world);
 
@@ -264,16 +519,19 @@ fn cornell_box_scene<T: Rng>(_rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
                             &up,
                             40.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
 
-    (world, camera)
+    let mut scene = Scene::new(Arc::new(world), camera);
+    scene.lights = lights;
+    scene
 }
 
-fn final_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
-    let mut world = Vec::<Rc<dyn Hittable>>::new();
+fn final_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> Scene {
+    let mut world = Vec::<Arc<dyn Hittable>>::new();
+    let mut lights = HittableList::default();
 
     // Boxes 1
-    let mut boxes1 = Vec::<Rc<dyn Hittable>>::new();
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.48, 0.83, 0.53)));
-    let ground = Rc::new(Lambertian::new(texture));
+    let mut boxes1 = Vec::<Arc<dyn Hittable>>::new();
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.48, 0.83, 0.53)));
+    let ground = Arc::new(Lambertian::new(texture));
 
     const BOXES_PER_SIDE: usize = 20;
     for i in 0..BOXES_PER_SIDE {
@@ -289,55 +547,57 @@ fn final_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
             let y1 = rng.gen_range(1.0..101.0);
             let z1 = z0 + w;
 
-            boxes1.push(Rc::new(AABox::new(&Point3::new(x0,y0,z0), &Point3::new(x1,y1,z1), ground.clone())));
+            boxes1.push(Arc::new(AABox::new(&Point3::new(x0,y0,z0), &Point3::new(x1,y1,z1), ground.clone())));
         }
     }
-    let boxes1 = Rc::new(BVH::new(boxes1));
+    let boxes1 = Arc::new(BVH::new(boxes1));
     world.push(boxes1);
 
     // Light
-    let light = Rc::new(DiffuseLight::new(Rc::new(SolidTexture::new(&Color::new(7.0, 7.0, 7.0)))));
-    world.push(Rc::new(AARect::new_xz(123.0, 423.0, 147.0, 412.0, 554.0, light)));
+    let light = Arc::new(DiffuseLight::new(Arc::new(SolidTexture::new(&Color::new(7.0, 7.0, 7.0)))));
+    let light = Arc::new(AARect::new_xz(123.0, 423.0, 147.0, 412.0, 554.0, light));
+    world.push(light.clone());
+    lights.add(light);
 
     // Moving sphere
     let center0 = Point3::new(400.0, 400.0, 200.0);
     let center1 = center0 + Vec3::new(30.0, 0.0, 0.0);
-    let moving_sphere_material = Rc::new(Lambertian::new(Rc::new(SolidTexture::new(&Color::new(0.7, 0.3, 0.1)))));
-    world.push(Rc::new(AnimatedSphere::new(&center0, &center1, 1.0, 50.0, moving_sphere_material)));
+    let moving_sphere_material = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.7, 0.3, 0.1)))));
+    world.push(Arc::new(AnimatedSphere::new(&center0, &center1, 1.0, 50.0, moving_sphere_material)));
 
     // Spheres
-    world.push(Rc::new(Sphere::new(&Point3::new(260.0, 150.0, 45.0), 50.0, Rc::new(Dielectric::new(1.5)))));
-    world.push(Rc::new(Sphere::new(
+    world.push(Arc::new(Sphere::new(&Point3::new(260.0, 150.0, 45.0), 50.0, Arc::new(Dielectric::new(1.5)))));
+    world.push(Arc::new(Sphere::new(
         &Point3::new(0.0, 150.0, 145.0), 50.0, 
-        Rc::new(Metal::new(Rc::new(SolidTexture::new(&Color::new(0.8, 0.8, 0.9))), 1.0))
+        Arc::new(Metal::new(Arc::new(SolidTexture::new(&Color::new(0.8, 0.8, 0.9))), 1.0))
     )));
 
     // Volumes
-    let boundary = Rc::new(Sphere::new(&Point3::new(360.0, 150.0, 145.0), 70.0, Rc::new(Dielectric::new(1.5))));
+    let boundary = Arc::new(Sphere::new(&Point3::new(360.0, 150.0, 145.0), 70.0, Arc::new(Dielectric::new(1.5))));
     world.push(boundary.clone());
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.2, 0.4, 0.9)));
-    let material = Rc::new(Lambertian::new(texture));
-    world.push(Rc::new(ConstantMedium::new(boundary, material, 0.2)));
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.2, 0.4, 0.9)));
+    let material = Arc::new(Lambertian::new(texture));
+    world.push(Arc::new(ConstantMedium::new(boundary, material, 0.2)));
     
-    let boundary = Rc::new(Sphere::new(&Point3::new(0.0, 0.0, 0.0), 5000.0, Rc::new(Dielectric::new(1.5))));
-    let texture = Rc::new(SolidTexture::new(&Color::new(1.0, 1.0, 1.0)));
-    let material = Rc::new(Lambertian::new(texture));
-    world.push(Rc::new(ConstantMedium::new(boundary, material, 0.0001)));
+    let boundary = Arc::new(Sphere::new(&Point3::new(0.0, 0.0, 0.0), 5000.0, Arc::new(Dielectric::new(1.5))));
+    let texture = Arc::new(SolidTexture::new(&Color::new(1.0, 1.0, 1.0)));
+    let material = Arc::new(Lambertian::new(texture));
+    world.push(Arc::new(ConstantMedium::new(boundary, material, 0.0001)));
 
-    let emat = Rc::new(Lambertian::new(Rc::new(ImageTexture::new("earthmap.jpg"))));
-    world.push(Rc::new(Sphere::new(&Point3::new(400.0, 200.0, 400.0), 100.0, emat)));
+    let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::new("earthmap.jpg"))));
+    world.push(Arc::new(Sphere::new(&Point3::new(400.0, 200.0, 400.0), 100.0, emat)));
     
     // Boxes 2
-    let mut boxes2 = Vec::<Rc<dyn Hittable>>::new();
-    let texture = Rc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)));
-    let white = Rc::new(Lambertian::new(texture));
+    let mut boxes2 = Vec::<Arc<dyn Hittable>>::new();
+    let texture = Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)));
+    let white = Arc::new(Lambertian::new(texture));
 
     let translation = Vec3::new(-100.0, 270.0, 395.0);
     const NUM_SPHERE: usize = 1000;
     for _ in 0..NUM_SPHERE {
-        boxes2.push(Rc::new(Sphere::new(&(Point3::random_in(0.0, 165.0) + translation), 10.0, white.clone())));
+        boxes2.push(Arc::new(Sphere::new(&(Point3::random_in(0.0, 165.0, rng) + translation), 10.0, white.clone())));
     }
-    let boxes2 = Rc::new(BVH::new(boxes2));
+    let boxes2 = Arc::new(BVH::new(boxes2));
     world.push(boxes2);
 
     let world = BVH::new(world);
@@ -354,6 +614,8 @@ fn final_scene<T: Rng>(rng: &mut T, aspect_ratio: f64) -> (BVH, Camera) {
                             &up,
                             40.0, aspect_ratio, APERTURE, DIST_TO_FOCUS, SHUTTER_DURATION);
 
-    (world, camera)
+    let mut scene = Scene::new(Arc::new(world), camera);
+    scene.lights = lights;
+    scene
 }
 
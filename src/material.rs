@@ -1,30 +1,67 @@
-use rand::{self,Rng};
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use super::vec3::{Vec3, Color, Point3};
 use super::ray::Ray;
 use super::hittable::HitRecord;
-use super::texture::Texture;
+use super::texture::{Texture, SolidTexture};
+use super::onb::Onb;
+use super::microfacet::Ggx;
 
-pub trait Material {
-    // return attenuation and scattered ray
-    fn scatter(&self, _ray: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+pub struct ScatterRecord {
+    pub ray: Ray,
+    // BSDF times cosine divided by pdf, the factor the scattered ray's color is multiplied by
+    pub attenuation: Color,
+    // Density, by solid angle, of sampling the scattered direction. Unused for specular samples
+    pub pdf: f64,
+    // Sampled from a delta lobe, which eval() and pdf() can't represent
+    pub specular: bool,
+}
+
+pub trait Material: Send + Sync {
+    // Samples a scattered ray for the incoming ray at the hit point
+    fn sample(&self, _ray: &Ray, _rec: &HitRecord, _rng: &mut dyn RngCore) -> Option<ScatterRecord> {
         None
     }
 
+    // BSDF times cosine for scattering the incoming ray into the unit direction wi,
+    // excluding specular lobes
+    fn eval(&self, _ray: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Color {
+        Color::default()
+    }
+
+    // Density, by solid angle, of sample() choosing the unit direction wi, excluding specular lobes
+    fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         // Black
         Color::default()
     }
+
+    // Absorption coefficient, per unit of distance, inside closed objects with this material. Rays
+    // passing into such an object travel through its interior until they pass out of it again.
+    fn absorption(&self) -> Option<Color> {
+        None
+    }
+
+    // Whether scattering depends on the wavelength of the ray, a path reaching such a material is
+    // traced at a single wavelength from then on
+    fn dispersive(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
-    albedo: Rc<dyn Texture>,
+    albedo: Arc<dyn Texture>,
 }
 
 #[allow(dead_code)]
 impl Lambertian {
-    pub fn new(albedo: Rc<dyn Texture>) -> Self {
+    pub fn new(albedo: Arc<dyn Texture>) -> Self {
         Self {
             albedo
         }
@@ -32,25 +69,41 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let scatter_direction = rec.normal + random_unit_vector();
-        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        if scatter_direction.near_zero() {
-            Some((attenuation, Ray::new(&rec.p, &rec.normal, ray.t())))
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        // normal + random unit vector is cosine distributed
+        let scatter_direction = rec.normal + random_unit_vector(rng);
+        let scatter_direction = if scatter_direction.near_zero() {
+            rec.normal
         } else {
-            Some((attenuation, Ray::new(&rec.p, &scatter_direction.unit_vector(), ray.t())))
-        }   
+            scatter_direction.unit_vector()
+        };
+
+        Some(ScatterRecord {
+            ray: Ray::new(&rec.p, &scatter_direction, ray.t()),
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: self.pdf(ray, rec, &scatter_direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray, rec, wi)
+    }
+
+    fn pdf(&self, _ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(*wi);
+        if cosine < 0.0 {0.0} else {cosine * std::f64::consts::FRAC_1_PI}
     }
 }
 
 pub struct Metal {
-    albedo: Rc<dyn Texture>,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 #[allow(dead_code)]
 impl Metal {
-    pub fn new(albedo: Rc<dyn Texture>, fuzz: f64) -> Self {
+    pub fn new(albedo: Arc<dyn Texture>, fuzz: f64) -> Self {
         Self {
             albedo,
             fuzz: if fuzz < 1.0 {fuzz} else {1.0},
@@ -59,20 +112,221 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    // The fuzzed reflection has no closed form density, so it's sampled like a specular lobe
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
         let reflected_direction = reflect(&ray.dir(), &rec.normal);
-        let scatter = (reflected_direction + random_in_unit_sphere() * self.fuzz).unit_vector();
+        let scatter = (reflected_direction + random_in_unit_sphere(rng) * self.fuzz).unit_vector();
         if scatter.dot(rec.normal) > 0.0 {
-            let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-            Some((attenuation, Ray::new(&rec.p, &scatter, ray.t())))
+            Some(ScatterRecord {
+                ray: Ray::new(&rec.p, &scatter, ray.t()),
+                attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+                pdf: 0.0,
+                specular: true,
+            })
         } else {
             None
         }
     }
 }
 
+// Metals with measured complex indices of refraction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalPreset {
+    Gold,
+    Copper,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+    Silver,
+}
+
+impl FromStr for MetalPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gold" => Ok(MetalPreset::Gold),
+            "copper" => Ok(MetalPreset::Copper),
+            "aluminium" | "aluminum" => Ok(MetalPreset::Aluminium),
+            "silver" => Ok(MetalPreset::Silver),
+            _ => Err(format!("Unknown metal {}", s)),
+        }
+    }
+}
+
+impl MetalPreset {
+    // Real and imaginary parts of the index of refraction, eta and k, sampled at red, green and
+    // blue wavelengths of about 650, 550 and 450 nm
+    pub fn ior(&self) -> (Color, Color) {
+        match self {
+            MetalPreset::Gold => (Color::new(0.143119, 0.374957, 1.44248), Color::new(3.98316, 2.38572, 1.60322)),
+            MetalPreset::Copper => (Color::new(0.200438, 0.924033, 1.10221), Color::new(3.91295, 2.45285, 2.14219)),
+            MetalPreset::Aluminium => (Color::new(1.65746, 0.880369, 0.521229), Color::new(9.22387, 6.26952, 4.837)),
+            MetalPreset::Silver => (Color::new(0.155265, 0.116723, 0.138342), Color::new(4.82835, 3.12225, 2.14696)),
+        }
+    }
+}
+
+// Rough metal, a GGX microfacet reflector whose Fresnel reflectance follows the complex index of
+// refraction eta + ik of every color channel. Roughness 0 is a perfect mirror.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+}
+
+#[allow(dead_code)]
+impl Conductor {
+    pub fn new(eta: &Color, k: &Color, roughness: f64) -> Self {
+        Self {
+            eta: *eta,
+            k: *k,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+
+    pub fn preset(metal: MetalPreset, roughness: f64) -> Self {
+        let (eta, k) = metal.ior();
+        Self::new(&eta, &k, roughness)
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_theta, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_theta, self.eta.z(), self.k.z()),
+        )
+    }
+
+    // The outgoing and incoming directions and their half vector in the local frame of the
+    // normal, if both are above the surface and the lobe isn't a mirror
+    fn local_half_vector(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<(Vec3, Vec3, Vec3)> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+        let basis = Onb::new(&rec.normal);
+        let wo = basis.to_basis(&-ray.dir().unit_vector());
+        let wi = basis.to_basis(wi);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return None;
+        }
+        Some((wo, wi, (wo + wi).unit_vector()))
+    }
+}
+
+impl Material for Conductor {
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let dir = ray.dir().unit_vector();
+        if self.distribution.is_smooth() {
+            return Some(ScatterRecord {
+                ray: Ray::new(&rec.p, &reflect(&dir, &rec.normal), ray.t()),
+                attenuation: self.fresnel(-dir.dot(rec.normal)),
+                pdf: 0.0,
+                specular: true,
+            });
+        }
+
+        let basis = Onb::new(&rec.normal);
+        let wo = basis.to_basis(&-dir);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let h = self.distribution.sample_visible_normal(&wo, rng.gen(), rng.gen());
+        let wi = reflect(&-wo, &h);
+        // Reflected into the surface, the energy that would scatter between microfacets is lost
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        // F D G / (4 cos_o) over the visible normal density G1 D / (4 cos_o)
+        let masking = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+        Some(ScatterRecord {
+            ray: Ray::new(&rec.p, &basis.local(&wi), ray.t()),
+            attenuation: self.fresnel(wo.dot(h)) * masking,
+            pdf: self.distribution.visible_normal_pdf(&wo, &h) / (4.0 * wo.dot(h)),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        match self.local_half_vector(ray, rec, wi) {
+            Some((wo, wi, h)) => {
+                let d = self.distribution.d(&h);
+                let g = self.distribution.g(&wo, &wi);
+                self.fresnel(wo.dot(h)) * (d * g / (4.0 * wo.z()))
+            },
+            None => Color::default(),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        match self.local_half_vector(ray, rec, wi) {
+            Some((wo, _, h)) => self.distribution.visible_normal_pdf(&wo, &h) / (4.0 * wo.dot(h)),
+            None => 0.0,
+        }
+    }
+}
+
+// Wavelength of the Fraunhofer d line in nanometers, where glasses quote their index of refraction
+pub const D_LINE_WAVELENGTH: f64 = 587.56;
+
+// Index of refraction that depends on the wavelength of the light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ², with λ in micrometers
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i), with λ in micrometers and c_i in square micrometers
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+#[allow(dead_code)]
+impl Dispersion {
+    // Schott N-BK7 borosilicate crown glass, n = 1.5168 at the d line
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    // Diamond, n = 2.4175 at the d line and dispersing about five times as strongly as BK7
+    pub fn diamond() -> Self {
+        Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }
+    }
+
+    // Index of refraction at the wavelength in nanometers
+    pub fn index(&self, wavelength: f64) -> f64 {
+        let micrometers = wavelength / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy {a, b} => a + b / l2,
+            Dispersion::Sellmeier {b, c} => {
+                let n2 = 1.0 + b.iter().zip(c.iter()).map(|(b, c)| b * l2 / (l2 - c)).sum::<f64>();
+                n2.sqrt()
+            },
+        }
+    }
+}
+
+// Glass, perfectly smooth unless given a roughness texture. Rough glass is a GGX microfacet
+// surface that both reflects and transmits, like frosted glass, and wherever its roughness is 0
+// it scatters exactly like smooth glass. Colored glass absorbs light travelling inside it, and
+// dispersive glass refracts every wavelength at its own angle.
 pub struct Dielectric {
     index_of_refraction: f64,
+    // Perceptual roughness in [0, 1], read from the luminance of the texture
+    roughness: Option<Arc<dyn Texture>>,
+    absorption: Option<Color>,
+    dispersion: Option<Dispersion>,
 }
 
 #[allow(dead_code)]
@@ -80,32 +334,473 @@ impl Dielectric {
     pub fn new(index_of_refraction: f64) -> Self {
         Self {
             index_of_refraction,
+            roughness: None,
+            absorption: None,
+            dispersion: None,
+        }
+    }
+
+    pub fn with_roughness(index_of_refraction: f64, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            index_of_refraction,
+            roughness: Some(roughness),
+            absorption: None,
+            dispersion: None,
+        }
+    }
+
+    // Light travelling a distance d inside the glass keeps exp(-absorption * d) of every channel
+    pub fn absorbing(mut self, absorption: &Color) -> Self {
+        self.absorption = Some(*absorption);
+        self
+    }
+
+    // Rays that carry a wavelength refract with the index of the dispersion at it, others keep
+    // the index the glass was made with
+    pub fn dispersing(mut self, dispersion: Dispersion) -> Self {
+        self.dispersion = Some(dispersion);
+        self
+    }
+
+    // The absorption coefficient of glass that white light passes through as the color tint,
+    // with every channel in (0, 1], after travelling the distance inside it
+    pub fn tint_absorption(tint: &Color, distance: f64) -> Color {
+        Color::new(-tint.x().ln(), -tint.y().ln(), -tint.z().ln()) / distance
+    }
+
+    // The microfacet distribution at the hit point, none where the surface is smooth
+    fn distribution(&self, rec: &HitRecord) -> Option<Ggx> {
+        let roughness = self.roughness.as_ref()?.value(rec.u, rec.v, &rec.p).luminance();
+        Some(Ggx::from_roughness(roughness)).filter(|d| !d.is_smooth())
+    }
+
+    fn refract_ratio(&self, ray: &Ray, rec: &HitRecord) -> f64 {
+        let index_of_refraction = match (self.dispersion, ray.wavelength()) {
+            (Some(dispersion), Some(wavelength)) => dispersion.index(wavelength),
+            _ => self.index_of_refraction,
+        };
+        if rec.front_face {1.0 / index_of_refraction} else {index_of_refraction}
+    }
+
+    // BSDF times cosine and density of sampling wi, both in the local frame of the normal with wo
+    // above the surface. Like smooth glass, transmitted radiance isn't scaled by the squared ratio
+    // of the indices, which cancels out for rays that leave the object again.
+    fn evaluate_rough(distribution: &Ggx, refract_ratio: f64, wo: &Vec3, wi: &Vec3) -> (f64, f64) {
+        let h = match microfacet_normal(wo, wi, refract_ratio) {
+            Some(h) => h,
+            None => return (0.0, 0.0),
+        };
+        let wo_dot_h = wo.dot(h);
+        let wi_dot_h = wi.dot(h);
+
+        let reflectance = refract(&-*wo, &h, refract_ratio).map_or(1.0, |(_, r)| r);
+        let d = distribution.d(&h);
+        let g = distribution.g(wo, wi);
+        let visible_pdf = distribution.visible_normal_pdf(wo, &h);
+        if wi.z() > 0.0 {
+            (reflectance * d * g / (4.0 * wo.z()), reflectance * visible_pdf / (4.0 * wo_dot_h))
+        } else {
+            let denom = (wi_dot_h + wo_dot_h * refract_ratio).powi(2);
+            let transmittance = 1.0 - reflectance;
+            (
+                transmittance * d * g * (wi_dot_h * wo_dot_h).abs() / (wo.z() * denom),
+                transmittance * visible_pdf * wi_dot_h.abs() / denom,
+            )
         }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let refract_ratio = if rec.front_face {1.0 / self.index_of_refraction} else {self.index_of_refraction};
-        let refraction = refract(&ray.dir(), &rec.normal, refract_ratio);
-
-        let mut rng = rand::thread_rng();
-        let scatter_direction = match refraction {
-            Some((r, reflectance)) if reflectance > rng.gen() => r,
-            _ => reflect(&ray.dir(), &rec.normal),
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let refract_ratio = self.refract_ratio(ray, rec);
+        let distribution = match self.distribution(rec) {
+            Some(distribution) => distribution,
+            None => {
+                let refraction = refract(&ray.dir(), &rec.normal, refract_ratio);
+
+                // Reflects with the probability of the Fresnel reflectance
+                let scatter_direction = match refraction {
+                    Some((r, reflectance)) if rng.gen::<f64>() >= reflectance => r,
+                    _ => reflect(&ray.dir(), &rec.normal),
+                };
+
+                return Some(ScatterRecord {
+                    ray: Ray::new(&rec.p, &scatter_direction, ray.t()),
+                    attenuation: Color::new(1.0, 1.0, 1.0),
+                    pdf: 0.0,
+                    specular: true,
+                });
+            },
         };
 
-        Some((Color::new(1.0, 1.0, 1.0), Ray::new(&rec.p, &scatter_direction, ray.t())))
+        let basis = Onb::new(&rec.normal);
+        let wo = basis.to_basis(&-ray.dir().unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let wi = sample_rough_dielectric(&distribution, refract_ratio, &wo, rng)?;
+        let (f, pdf) = Dielectric::evaluate_rough(&distribution, refract_ratio, &wo, &wi);
+        if f <= 0.0 || pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            ray: Ray::new(&rec.p, &basis.local(&wi), ray.t()),
+            attenuation: Color::new(1.0, 1.0, 1.0) * (f / pdf),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        match self.distribution(rec) {
+            Some(distribution) => {
+                let basis = Onb::new(&rec.normal);
+                let wo = basis.to_basis(&-ray.dir().unit_vector());
+                let (f, _) = Dielectric::evaluate_rough(&distribution, self.refract_ratio(ray, rec), &wo, &basis.to_basis(wi));
+                Color::new(f, f, f)
+            },
+            None => Color::default(),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        match self.distribution(rec) {
+            Some(distribution) => {
+                let basis = Onb::new(&rec.normal);
+                let wo = basis.to_basis(&-ray.dir().unit_vector());
+                Dielectric::evaluate_rough(&distribution, self.refract_ratio(ray, rec), &wo, &basis.to_basis(wi)).1
+            },
+            None => 0.0,
+        }
+    }
+
+    fn absorption(&self) -> Option<Color> {
+        self.absorption
+    }
+
+    fn dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+// Principled roughness is kept above this, so its specular lobes stay glossy ones that eval() and
+// pdf() cover. At this roughness reflections already look like a mirror's.
+const MIN_PRINCIPLED_ROUGHNESS: f64 = 0.04;
+// Perceptual roughness of the principled clear coat
+const CLEARCOAT_ROUGHNESS: f64 = 0.1;
+// Normal incidence reflectance of the clear coat, that of ior 1.5
+const CLEARCOAT_REFLECTANCE: f64 = 0.04;
+
+// Parameters of the principled material, read from their textures at the hit point. All but
+// base_color and ior are scalars in [0, 1], read from the luminance of their textures.
+pub struct PrincipledParameters {
+    pub base_color: Arc<dyn Texture>,
+    // 0 is a dielectric, 1 a metal whose specular reflection takes on base_color
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // Normal incidence specular reflectance of the dielectric part, 0.08 times specular, so 0.5
+    // is the 4% of ior 1.5
+    pub specular: Arc<dyn Texture>,
+    // Tints the dielectric specular reflection towards base_color
+    pub specular_tint: Arc<dyn Texture>,
+    // Extra reflection at grazing angles, for cloth
+    pub sheen: Arc<dyn Texture>,
+    // A second, colorless and glossy specular layer on top
+    pub clearcoat: Arc<dyn Texture>,
+    // How much of the dielectric part transmits light like glass instead of reflecting it diffusely
+    pub transmission: Arc<dyn Texture>,
+    // Index of refraction of the transmitting part, above 0
+    pub ior: Arc<dyn Texture>,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> Self {
+        let value = |v: f64| -> Arc<dyn Texture> {Arc::new(SolidTexture::new(&Color::new(v, v, v)))};
+        Self {
+            base_color: value(0.8),
+            metallic: value(0.0),
+            roughness: value(0.5),
+            specular: value(0.5),
+            specular_tint: value(0.0),
+            sheen: value(0.0),
+            clearcoat: value(0.0),
+            transmission: value(0.0),
+            ior: value(1.5),
+        }
+    }
+}
+
+// Disney's principled BSDF (Burley, "Physically Based Shading at Disney", 2012, extended with
+// transmission in 2015). A diffuse lobe with retro-reflection and sheen, GGX specular reflection,
+// GGX transmission and a clear coat, blended by the parameters. sample() picks one lobe in
+// proportion to its estimated contribution and eval() and pdf() cover them all. From inside a
+// transmitting object it is rough glass. Transmission takes on the square root of base_color both
+// entering and leaving, so light passing through an object is tinted by base_color once, whichever
+// way it goes.
+pub struct Principled {
+    parameters: PrincipledParameters,
+}
+
+#[allow(dead_code)]
+impl Principled {
+    pub fn new(parameters: PrincipledParameters) -> Self {
+        Self {
+            parameters
+        }
+    }
+
+    // The lobes at the hit point, none if the ray arrives from below the shading normal
+    fn lobes(&self, ray: &Ray, rec: &HitRecord) -> Option<PrincipledLobes> {
+        let parameters = &self.parameters;
+        let scalar = |texture: &Arc<dyn Texture>| texture.value(rec.u, rec.v, &rec.p).luminance();
+        let unit = |texture: &Arc<dyn Texture>| scalar(texture).clamp(0.0, 1.0);
+
+        let basis = Onb::new(&rec.normal);
+        let wo = basis.to_basis(&-ray.dir().unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let base_color = parameters.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = unit(&parameters.metallic);
+        let roughness = unit(&parameters.roughness).max(MIN_PRINCIPLED_ROUGHNESS);
+        let transmission = unit(&parameters.transmission);
+        let ior = scalar(&parameters.ior);
+        // Scene files reject constant ones, textures can still go there
+        if !(ior > 0.0 && ior.is_finite()) {
+            return None;
+        }
+        let distribution = Ggx::from_roughness(roughness);
+        let transmission_tint = Color::new(base_color.x().max(0.0).sqrt(), base_color.y().max(0.0).sqrt(),
+                                           base_color.z().max(0.0).sqrt());
+        if !rec.front_face && transmission > 0.0 {
+            return Some(PrincipledLobes::Inside {
+                basis, wo, distribution, transmission_tint,
+                refract_ratio: ior,
+            });
+        }
+
+        // Hue and saturation of the base color, at full luminance
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {base_color / luminance} else {Color::new(1.0, 1.0, 1.0)};
+        let white = Color::new(1.0, 1.0, 1.0);
+        let dielectric_specular = lerp(&white, &tint, unit(&parameters.specular_tint)) * (0.08 * unit(&parameters.specular));
+        let specular_color = lerp(&dielectric_specular, &base_color, metallic);
+        let sheen = unit(&parameters.sheen);
+        let sheen_color = lerp(&white, &tint, 0.5) * sheen;
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let clearcoat_weight = 0.25 * unit(&parameters.clearcoat);
+
+        // Sample every lobe about as often as it reflects light towards wo
+        let mut probabilities = [
+            diffuse_weight * (luminance + sheen).min(1.0),
+            schlick(&specular_color, wo.z()).luminance(),
+            transmission_weight,
+            clearcoat_weight * schlick(&Color::new(CLEARCOAT_REFLECTANCE, CLEARCOAT_REFLECTANCE, CLEARCOAT_REFLECTANCE), wo.z()).x(),
+        ];
+        let total: f64 = probabilities.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        for p in probabilities.iter_mut() {
+            *p /= total;
+        }
+
+        Some(PrincipledLobes::Outside {
+            basis, wo, distribution, base_color, roughness, specular_color, sheen_color, transmission_tint,
+            diffuse_weight, transmission_weight, clearcoat_weight, probabilities,
+            clearcoat_distribution: Ggx::from_roughness(CLEARCOAT_ROUGHNESS),
+            refract_ratio: 1.0 / ior,
+        })
+    }
+}
+
+// The principled lobes at a hit point, in the local frame of the shading normal
+enum PrincipledLobes {
+    // Inside a transmitting object, where the material is rough glass
+    Inside {
+        basis: Onb,
+        wo: Vec3,
+        distribution: Ggx,
+        // Of light leaving the object, not of light reflected back into it
+        transmission_tint: Color,
+        refract_ratio: f64,
+    },
+    Outside {
+        basis: Onb,
+        wo: Vec3,
+        distribution: Ggx,
+        clearcoat_distribution: Ggx,
+        base_color: Color,
+        roughness: f64,
+        // Normal incidence reflectance of the specular lobe
+        specular_color: Color,
+        sheen_color: Color,
+        transmission_tint: Color,
+        diffuse_weight: f64,
+        transmission_weight: f64,
+        clearcoat_weight: f64,
+        refract_ratio: f64,
+        // Of sampling the diffuse, specular, transmission and clear coat lobes
+        probabilities: [f64; 4],
+    },
+}
+
+impl PrincipledLobes {
+    fn basis(&self) -> &Onb {
+        match self {
+            PrincipledLobes::Inside {basis, ..} | PrincipledLobes::Outside {basis, ..} => basis,
+        }
+    }
+
+    // Local direction wi, drawing one value to pick a lobe and two to sample it
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3> {
+        match self {
+            PrincipledLobes::Inside {wo, distribution, refract_ratio, ..} => {
+                sample_rough_dielectric(distribution, *refract_ratio, wo, rng)
+            },
+            PrincipledLobes::Outside {wo, distribution, clearcoat_distribution, refract_ratio, probabilities, ..} => {
+                let u: f64 = rng.gen();
+                if u < probabilities[0] {
+                    // normal + random unit vector is cosine distributed
+                    let wi = Vec3::new(0.0, 0.0, 1.0) + random_unit_vector(rng);
+                    Some(if wi.near_zero() {Vec3::new(0.0, 0.0, 1.0)} else {wi.unit_vector()})
+                } else if u < probabilities[0] + probabilities[1] {
+                    let h = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+                    Some(reflect(&-*wo, &h)).filter(|r| r.z() > 0.0)
+                } else if u < probabilities[0] + probabilities[1] + probabilities[2] {
+                    let h = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+                    refract(&-*wo, &h, *refract_ratio).map(|(r, _)| r).filter(|r| r.z() < 0.0)
+                } else {
+                    let h = clearcoat_distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+                    Some(reflect(&-*wo, &h)).filter(|r| r.z() > 0.0)
+                }
+            },
+        }
+    }
+
+    // BSDF times cosine, summed over the lobes, for the local direction wi
+    fn eval(&self, wi: &Vec3) -> Color {
+        match self {
+            PrincipledLobes::Inside {wo, distribution, transmission_tint, refract_ratio, ..} => {
+                let (f, _) = Dielectric::evaluate_rough(distribution, *refract_ratio, wo, wi);
+                if wi.z() < 0.0 {*transmission_tint * f} else {Color::new(f, f, f)}
+            },
+            PrincipledLobes::Outside {
+                wo, distribution, clearcoat_distribution, base_color, roughness, specular_color, sheen_color,
+                transmission_tint, diffuse_weight, transmission_weight, clearcoat_weight, refract_ratio, ..
+            } => {
+                let mut f = Color::default();
+                if wi.z() > 0.0 {
+                    let h = (*wo + *wi).unit_vector();
+                    let cos_d = wi.dot(h);
+                    if *diffuse_weight > 0.0 {
+                        // Diffuse with retro-reflection, brighter at grazing angles on rough surfaces
+                        let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+                        let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z())) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()));
+                        let diffuse = *base_color * (retro * std::f64::consts::FRAC_1_PI);
+                        let sheen = *sheen_color * schlick_weight(cos_d);
+                        f += (diffuse + sheen) * (diffuse_weight * wi.z());
+                    }
+
+                    let d = distribution.d(&h);
+                    let g = distribution.g(wo, wi);
+                    f += schlick(specular_color, wo.dot(h)) * (d * g / (4.0 * wo.z()));
+
+                    if *clearcoat_weight > 0.0 {
+                        let d = clearcoat_distribution.d(&h);
+                        let g = clearcoat_distribution.g(wo, wi);
+                        let reflectance = CLEARCOAT_REFLECTANCE + (1.0 - CLEARCOAT_REFLECTANCE) * schlick_weight(wo.dot(h));
+                        let clearcoat = clearcoat_weight * reflectance * d * g / (4.0 * wo.z());
+                        f += Color::new(clearcoat, clearcoat, clearcoat);
+                    }
+                } else if *transmission_weight > 0.0 {
+                    if let Some(h) = microfacet_normal(wo, wi, *refract_ratio) {
+                        let wo_dot_h = wo.dot(h);
+                        let wi_dot_h = wi.dot(h);
+                        let reflectance = refract(&-*wo, &h, *refract_ratio).map_or(1.0, |(_, r)| r);
+                        let denom = (wi_dot_h + wo_dot_h * refract_ratio).powi(2);
+                        let d = distribution.d(&h);
+                        let g = distribution.g(wo, wi);
+                        let transmitted = transmission_weight * (1.0 - reflectance) * d * g
+                            * (wi_dot_h * wo_dot_h).abs() / (wo.z() * denom);
+                        f += *transmission_tint * transmitted;
+                    }
+                }
+                f
+            },
+        }
+    }
+
+    // Density of sample() choosing the local direction wi
+    fn pdf(&self, wi: &Vec3) -> f64 {
+        match self {
+            PrincipledLobes::Inside {wo, distribution, refract_ratio, ..} => {
+                Dielectric::evaluate_rough(distribution, *refract_ratio, wo, wi).1
+            },
+            PrincipledLobes::Outside {wo, distribution, clearcoat_distribution, refract_ratio, probabilities, ..} => {
+                if wi.z() > 0.0 {
+                    let h = (*wo + *wi).unit_vector();
+                    let jacobian = 1.0 / (4.0 * wo.dot(h));
+                    probabilities[0] * wi.z() * std::f64::consts::FRAC_1_PI
+                        + probabilities[1] * distribution.visible_normal_pdf(wo, &h) * jacobian
+                        + probabilities[3] * clearcoat_distribution.visible_normal_pdf(wo, &h) * jacobian
+                } else {
+                    match microfacet_normal(wo, wi, *refract_ratio) {
+                        Some(h) if probabilities[2] > 0.0 => {
+                            let denom = (wi.dot(h) + wo.dot(h) * refract_ratio).powi(2);
+                            probabilities[2] * distribution.visible_normal_pdf(wo, &h) * wi.dot(h).abs() / denom
+                        },
+                        _ => 0.0,
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let lobes = self.lobes(ray, rec)?;
+        let wi = lobes.sample(rng)?;
+
+        // Weighted by all lobes that could have chosen wi, not just the sampled one
+        let pdf = lobes.pdf(&wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            ray: Ray::new(&rec.p, &lobes.basis().local(&wi), ray.t()),
+            attenuation: lobes.eval(&wi) / pdf,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        match self.lobes(ray, rec) {
+            Some(lobes) => lobes.eval(&lobes.basis().to_basis(wi)),
+            None => Color::default(),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        match self.lobes(ray, rec) {
+            Some(lobes) => lobes.pdf(&lobes.basis().to_basis(wi)),
+            None => 0.0,
+        }
     }
 }
 
 pub struct DiffuseLight {
-    emit: Rc<dyn Texture>,
+    emit: Arc<dyn Texture>,
 }
 
 #[allow(dead_code)]
 impl DiffuseLight {
-    pub fn new(emit: Rc<dyn Texture>) -> Self {
+    pub fn new(emit: Arc<dyn Texture>) -> Self {
         Self {
             emit
         }
@@ -119,12 +814,12 @@ impl Material for DiffuseLight {
 }
 
 pub struct Isotropic {
-    albedo: Rc<dyn Texture>,
+    albedo: Arc<dyn Texture>,
 }
 
 #[allow(dead_code)]
 impl Isotropic {
-    pub fn new(albedo: Rc<dyn Texture>) -> Self {
+    pub fn new(albedo: Arc<dyn Texture>) -> Self {
         Self {
             albedo
         }
@@ -132,24 +827,68 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        let ray = Ray::new(&ray.origin(), &random_in_unit_sphere(), ray.t());
-        Some((attenuation, ray))
+    fn sample(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let scatter_direction = random_unit_vector(rng);
+        Some(ScatterRecord {
+            ray: Ray::new(&rec.p, &scatter_direction, ray.t()),
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: self.pdf(ray, rec, &scatter_direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray, rec, wi)
+    }
+
+    fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _wi: &Vec3) -> f64 {
+        // Uniform over the sphere of directions
+        0.25 * std::f64::consts::FRAC_1_PI
     }
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = Vec3::random_in(-1.0, 1.0);
-        if p.length_squared() < 1.0 {
-            break p;
-        }
+// These map uniform numbers directly instead of rejection sampling, so every call draws the same
+// number of values and a stratified sampler's values stay stratified
+fn random_in_unit_sphere(rng: &mut dyn RngCore) -> Vec3 {
+    let direction = random_unit_vector(rng);
+    direction * rng.gen::<f64>().cbrt()
+}
+
+fn random_unit_vector(rng: &mut dyn RngCore) -> Vec3 {
+    let z = 1.0 - 2.0 * rng.gen::<f64>();
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Samples a visible microfacet normal of rough glass and reflects wo off it with the probability
+// of the Fresnel reflectance, or refracts wo through it. Local to the surface with wo above it.
+// None when the direction ends up on the wrong side of the surface, which the pdf doesn't cover.
+fn sample_rough_dielectric(distribution: &Ggx, refract_ratio: f64, wo: &Vec3, rng: &mut dyn RngCore) -> Option<Vec3> {
+    let h = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+    match refract(&-*wo, &h, refract_ratio) {
+        Some((r, reflectance)) if rng.gen::<f64>() >= reflectance => Some(r).filter(|r| r.z() < 0.0),
+        _ => Some(reflect(&-*wo, &h)).filter(|r| r.z() > 0.0),
     }
 }
 
-fn random_unit_vector() -> Vec3 {
-    random_in_unit_sphere().unit_vector()
+// The microfacet normal that reflects wo into wi above the surface or refracts it into wi below,
+// local to the surface with wo above it. None if it would face away from either direction.
+fn microfacet_normal(wo: &Vec3, wi: &Vec3, refract_ratio: f64) -> Option<Vec3> {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return None;
+    }
+
+    let reflected = wi.z() > 0.0;
+    let h = if reflected {*wo + *wi} else {*wo + *wi / refract_ratio};
+    if h.near_zero() {
+        return None;
+    }
+    let h = if h.z() < 0.0 {-h.unit_vector()} else {h.unit_vector()};
+    if wo.dot(h) <= 0.0 || (wi.dot(h) > 0.0) != reflected {
+        return None;
+    }
+    Some(h)
 }
 
 fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
@@ -170,9 +909,374 @@ fn refract(v: &Vec3, n: &Vec3, etai_over_etat: f64) -> Option<(Vec3, f64)> {
     Some((r_out_perp + r_out_parallel, reflectance(cos_theta, etai_over_etat)))
 } 
 
+// (1 - cos_theta)^5, how Schlick's approximation moves reflectance towards 1 at grazing angles
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+// Schlick's approximation for reflectance, per channel, from the reflectance at normal incidence
+fn schlick(normal_reflectance: &Color, cos_theta: f64) -> Color {
+    lerp(normal_reflectance, &Color::new(1.0, 1.0, 1.0), schlick_weight(cos_theta))
+}
+
+fn lerp(a: &Color, b: &Color, t: f64) -> Color {
+    *a * (1.0 - t) + *b * t
+}
+
 fn reflectance(cos_theta: f64, etai_over_etat: f64) -> f64 {
     // Use Schlick's approximation for reflectance.
     let r0 = (1.0 - etai_over_etat) / (1.0 + etai_over_etat);
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cos_theta).powf(5.0)
-}
\ No newline at end of file
+}
+
+// Fresnel reflectance of unpolarized light arriving from vacuum at a conductor with complex index
+// of refraction eta + ik
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos_2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin_2 = 1.0 - cos_2;
+    let eta_2 = eta * eta;
+    let k_2 = k * k;
+
+    let t0 = eta_2 - k_2 - sin_2;
+    let a_2_plus_b_2 = (t0 * t0 + 4.0 * eta_2 * k_2).sqrt();
+    let t1 = a_2_plus_b_2 + cos_2;
+    let a = (0.5 * (a_2_plus_b_2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos_2 * a_2_plus_b_2 + sin_2 * sin_2;
+    let t4 = t2 * sin_2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::f64::consts::PI;
+
+    // Unit direction leaving the surface at polar angle theta in degrees, towards +x
+    fn direction(theta: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        Vec3::new(sin_theta, 0.0, cos_theta)
+    }
+
+    // A ray arriving at the origin from direction wo, on the z = 0 plane whose shading normal is +z.
+    // With front_face unset it hits the back of a surface whose outward normal is -z.
+    fn hit(material: &Arc<dyn Material>, wo: &Vec3, front_face: bool) -> (Ray, HitRecord) {
+        let ray = Ray::new(wo, &-*wo, 0.0);
+        let outward_normal = if front_face {Vec3::new(0.0, 0.0, 1.0)} else {Vec3::new(0.0, 0.0, -1.0)};
+        let rec = HitRecord::new(&ray, 1.0, &Point3::default(), &outward_normal, material.clone(), 0.5, 0.5);
+        assert_eq!(rec.front_face, front_face);
+        (ray, rec)
+    }
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+    }
+
+    // What sampling the material for a ray arriving from wo gives on average
+    struct SampleStats {
+        // Fraction of samples that scatter through a glossy lobe, and through a specular one
+        glossy: f64,
+        specular: f64,
+        // Mean weight of all samples, absorbed ones count as black
+        albedo: Color,
+    }
+
+    // Samples the material and checks every glossy sample against eval and pdf: sample's pdf is
+    // pdf(), and its weight is eval() / pdf()
+    fn check_samples(material: &Arc<dyn Material>, wo: &Vec3, front_face: bool, count: usize, rng: &mut StdRng) -> SampleStats {
+        let (ray, rec) = hit(material, wo, front_face);
+        let (mut glossy, mut specular) = (0, 0);
+        let mut albedo = Color::default();
+        for _ in 0..count {
+            let srec = match material.sample(&ray, &rec, rng) {
+                Some(srec) => srec,
+                None => continue,
+            };
+            albedo += srec.attenuation;
+            if srec.specular {
+                specular += 1;
+                continue;
+            }
+            glossy += 1;
+
+            let wi = srec.ray.dir().unit_vector();
+            let pdf = material.pdf(&ray, &rec, &wi);
+            assert!(pdf > 0.0, "sampled {:?} for {:?} has no density", wi, wo);
+            assert!(close(srec.pdf, pdf, 1e-6), "sampled pdf {} but pdf() is {} for {:?}", srec.pdf, pdf, wi);
+            let weight = material.eval(&ray, &rec, &wi) / pdf;
+            for i in 0..3 {
+                assert!(close(srec.attenuation[i], weight[i], 1e-6),
+                        "sampled weight {:?} but eval / pdf is {:?} for {:?}", srec.attenuation, weight, wi);
+            }
+        }
+        SampleStats {
+            glossy: glossy as f64 / count as f64,
+            specular: specular as f64 / count as f64,
+            albedo: albedo / count as f64,
+        }
+    }
+
+    // Integral of pdf() over the sphere of directions, by the midpoint rule on a grid of cells of
+    // equal solid angle, size by 2 size of them in height and azimuth around the y axis. The lobes
+    // of directions from the xz plane lie around the equator, where the cells are smallest. The
+    // glossy samples must account for all of it.
+    fn pdf_integral(material: &Arc<dyn Material>, wo: &Vec3, front_face: bool, size: usize) -> f64 {
+        let (ray, rec) = hit(material, wo, front_face);
+        let mut sum = 0.0;
+        for i in 0..size {
+            let y = 1.0 - 2.0 * (i as f64 + 0.5) / size as f64;
+            let r = (1.0 - y * y).sqrt();
+            for j in 0..2 * size {
+                let phi = PI * (j as f64 + 0.5) / size as f64;
+                sum += material.pdf(&ray, &rec, &Vec3::new(r * phi.cos(), y, r * phi.sin()));
+            }
+        }
+        4.0 * PI * sum / (2 * size * size) as f64
+    }
+
+    #[test]
+    fn conductor_samples_match_eval_and_pdf() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for &roughness in [0.3, 0.6, 1.0].iter() {
+            let gold: Arc<dyn Material> = Arc::new(Conductor::preset(MetalPreset::Gold, roughness));
+            for &theta in [0.0, 30.0, 60.0, 85.0].iter() {
+                let wo = direction(theta);
+                let stats = check_samples(&gold, &wo, true, 20_000, &mut rng);
+                assert_eq!(stats.specular, 0.0);
+                // Samples reflected below the surface are dropped, pdf() doesn't count them either
+                let integral = pdf_integral(&gold, &wo, true, 600);
+                assert!((integral - stats.glossy).abs() < 0.01,
+                        "roughness {} at {} degrees: pdf integrates to {} but {} of the samples are glossy",
+                        roughness, theta, integral, stats.glossy);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_white_furnace_never_gains_energy() {
+        // A conductor with a huge extinction coefficient reflects everything at every angle, so
+        // the albedo is the energy single scattering keeps. Rougher microfacets shadow each other
+        // more, at roughness 1 less than half of the energy is left at normal incidence.
+        let mut rng = StdRng::seed_from_u64(2);
+        let k = Color::new(1e4, 1e4, 1e4);
+        for &theta in [0.0, 45.0, 80.0].iter() {
+            let mut previous = 1.0;
+            for &roughness in [0.0, 0.2, 0.5, 1.0].iter() {
+                let mirror: Arc<dyn Material> = Arc::new(Conductor::new(&Color::new(1.0, 1.0, 1.0), &k, roughness));
+                let albedo = check_samples(&mirror, &direction(theta), true, 20_000, &mut rng).albedo;
+                if roughness == 0.0 {
+                    assert!(close(albedo.x(), 1.0, 1e-6), "a mirror at {} degrees reflects {:?}", theta, albedo);
+                }
+                assert!(albedo.x() <= previous + 0.01 && albedo.x() > 0.25,
+                        "roughness {} at {} degrees reflects {:?}", roughness, theta, albedo);
+                previous = albedo.x();
+            }
+        }
+
+        for &metal in [MetalPreset::Gold, MetalPreset::Copper, MetalPreset::Aluminium, MetalPreset::Silver].iter() {
+            let material: Arc<dyn Material> = Arc::new(Conductor::preset(metal, 0.5));
+            for &theta in [0.0, 60.0, 85.0].iter() {
+                let albedo = check_samples(&material, &direction(theta), true, 20_000, &mut rng).albedo;
+                assert!((0..3).all(|i| albedo[i] > 0.0 && albedo[i] <= 1.0), "{:?} reflects {:?}", metal, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_at_normal_incidence() {
+        // ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+        for &(eta, k) in [(0.143119, 3.98316), (1.65746, 9.22387), (1.5, 0.0)].iter() {
+            let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+            assert!(close(fresnel_conductor(1.0, eta, k), expected, 1e-9));
+            // Every conductor reflects everything at grazing incidence
+            assert!(close(fresnel_conductor(0.0, eta, k), 1.0, 1e-9));
+        }
+    }
+
+    fn rough_glass(roughness: f64) -> Arc<dyn Material> {
+        Arc::new(Dielectric::with_roughness(1.5, Arc::new(SolidTexture::new(&Color::new(roughness, roughness, roughness)))))
+    }
+
+    #[test]
+    fn rough_dielectric_samples_match_eval_and_pdf() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for &roughness in [0.3, 1.0].iter() {
+            let glass = rough_glass(roughness);
+            // Entering the glass, and leaving it, where grazing rays are totally reflected
+            for &front_face in [true, false].iter() {
+                for &theta in [0.0, 45.0, 85.0].iter() {
+                    let wo = direction(theta);
+                    let stats = check_samples(&glass, &wo, front_face, 20_000, &mut rng);
+                    assert_eq!(stats.specular, 0.0);
+                    // Reflection and transmission together, samples on the wrong side of the
+                    // surface are dropped and pdf() doesn't count them either
+                    let integral = pdf_integral(&glass, &wo, front_face, 600);
+                    assert!((integral - stats.glossy).abs() < 0.01,
+                            "roughness {} at {} degrees, front face {}: pdf integrates to {} but {} of the samples are glossy",
+                            roughness, theta, front_face, integral, stats.glossy);
+                    for i in 0..3 {
+                        assert!(stats.albedo[i] <= 1.0 + 1e-9, "roughness {} at {} degrees passes on {:?}", roughness, theta, stats.albedo);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_tends_to_smooth_glass() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let smooth = rough_glass(0.0);
+        let nearly_smooth = rough_glass(0.04);
+        const SAMPLES: usize = 20_000;
+        for &front_face in [true, false].iter() {
+            let refract_ratio = if front_face {1.0 / 1.5} else {1.5};
+            for &theta in [0.0, 30.0, 60.0].iter() {
+                let wo = direction(theta);
+                let (_, rec) = hit(&smooth, &wo, front_face);
+                let reflected = reflect(&-wo, &rec.normal);
+                let refracted = refract(&-wo, &rec.normal, refract_ratio);
+                let reflectance = refracted.map_or(1.0, |(_, r)| r);
+
+                let mut reflections = [0; 2];
+                let mut near_specular = [0; 2];
+                let mut albedo = [Color::default(); 2];
+                for (i, material) in [&smooth, &nearly_smooth].iter().enumerate() {
+                    let (ray, rec) = hit(material, &wo, front_face);
+                    for _ in 0..SAMPLES {
+                        let srec = match material.sample(&ray, &rec, &mut rng) {
+                            Some(srec) => srec,
+                            None => continue,
+                        };
+                        assert_eq!(srec.specular, i == 0);
+                        albedo[i] += srec.attenuation / SAMPLES as f64;
+                        let dir = srec.ray.dir().unit_vector();
+                        let specular_dir = if dir.z() > 0.0 {
+                            reflections[i] += 1;
+                            reflected
+                        } else {
+                            refracted.unwrap().0
+                        };
+                        if i == 0 {
+                            assert!((dir - specular_dir).length() < 1e-9, "{:?} is not {:?}", dir, specular_dir);
+                        }
+                        if (dir - specular_dir).length() < 0.05 {
+                            near_specular[i] += 1;
+                        }
+                    }
+                }
+
+                let case = format!("at {} degrees, front face {}", theta, front_face);
+                // The nearly smooth lobes keep all but the long tail of GGX within a few degrees
+                // of the mirror and refraction directions
+                assert!(near_specular[1] as f64 > 0.995 * SAMPLES as f64, "{}: {} near", case, near_specular[1]);
+                for i in 0..2 {
+                    let fraction = reflections[i] as f64 / SAMPLES as f64;
+                    assert!((fraction - reflectance).abs() < 0.015,
+                            "{}: {} reflected, Fresnel reflectance is {}", case, fraction, reflectance);
+                    assert!((albedo[i] - Color::new(1.0, 1.0, 1.0)).length() < 0.02, "{}: passes on {:?}", case, albedo[i]);
+                }
+            }
+        }
+    }
+
+    fn solid(r: f64, g: f64, b: f64) -> Arc<dyn Texture> {
+        Arc::new(SolidTexture::new(&Color::new(r, g, b)))
+    }
+
+    fn principled(base_color: Arc<dyn Texture>, change: impl FnOnce(&mut PrincipledParameters)) -> Arc<dyn Material> {
+        let mut parameters = PrincipledParameters {base_color, ..PrincipledParameters::default()};
+        change(&mut parameters);
+        Arc::new(Principled::new(parameters))
+    }
+
+    #[test]
+    fn principled_samples_match_eval_and_pdf() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let materials = [
+            ("plastic", principled(solid(0.1, 0.3, 0.8), |p| p.roughness = solid(0.2, 0.2, 0.2))),
+            ("metal", principled(solid(0.95, 0.64, 0.54), |p| {
+                p.metallic = solid(1.0, 1.0, 1.0);
+                p.roughness = solid(0.3, 0.3, 0.3);
+            })),
+            ("coated velvet", principled(solid(0.5, 0.05, 0.1), |p| {
+                p.roughness = solid(1.0, 1.0, 1.0);
+                p.sheen = solid(1.0, 1.0, 1.0);
+                p.clearcoat = solid(1.0, 1.0, 1.0);
+            })),
+            ("half glass", principled(solid(0.9, 0.9, 0.9), |p| {
+                p.roughness = solid(0.3, 0.3, 0.3);
+                p.transmission = solid(0.5, 0.5, 0.5);
+                p.clearcoat = solid(0.5, 0.5, 0.5);
+            })),
+        ];
+        for (name, material) in materials.iter() {
+            // Only transmitting materials are seen from inside
+            let sides: &[bool] = if *name == "half glass" {&[true, false]} else {&[true]};
+            for &front_face in sides.iter() {
+                // Not quite grazing, where the grid is too coarse for the narrow clear coat lobe
+                for &theta in [0.0, 45.0, 75.0].iter() {
+                    let wo = direction(theta);
+                    let stats = check_samples(material, &wo, front_face, 20_000, &mut rng);
+                    assert_eq!(stats.specular, 0.0);
+                    let integral = pdf_integral(material, &wo, front_face, 600);
+                    assert!((integral - stats.glossy).abs() < 0.01,
+                            "{} at {} degrees, front face {}: pdf integrates to {} but {} of the samples are glossy",
+                            name, theta, front_face, integral, stats.glossy);
+                }
+            }
+        }
+    }
+
+    // Mean weight of the samples that pass through the surface, over that of the blue channel
+    fn relative_transmission(material: &Arc<dyn Material>, front_face: bool, rng: &mut StdRng) -> Color {
+        let (ray, rec) = hit(material, &direction(0.0), front_face);
+        let mut sum = Color::default();
+        for _ in 0..10_000 {
+            if let Some(srec) = material.sample(&ray, &rec, rng) {
+                if srec.ray.dir().z() < 0.0 {
+                    sum += srec.attenuation;
+                }
+            }
+        }
+        assert!(sum.z() > 0.0);
+        sum / sum.z()
+    }
+
+    #[test]
+    fn principled_transmission_is_tinted_entering_and_leaving() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let glass = principled(solid(0.25, 0.5, 1.0), |p| {
+            p.roughness = solid(0.2, 0.2, 0.2);
+            p.transmission = solid(1.0, 1.0, 1.0);
+        });
+        let entering = relative_transmission(&glass, true, &mut rng);
+        let leaving = relative_transmission(&glass, false, &mut rng);
+        for i in 0..3 {
+            // Each crossing takes on half of the tint, in either direction
+            assert!(close(entering[i], leaving[i], 1e-9), "{:?} entering but {:?} leaving", entering, leaving);
+            assert!(close(entering[i] * leaving[i], [0.25, 0.5, 1.0][i], 1e-9));
+        }
+    }
+
+    #[test]
+    fn principled_with_invalid_ior_absorbs() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for &ior in [0.0, -1.5, f64::NAN].iter() {
+            let glass = principled(solid(1.0, 1.0, 1.0), |p| {
+                p.transmission = solid(1.0, 1.0, 1.0);
+                p.ior = solid(ior, ior, ior);
+            });
+            for &front_face in [true, false].iter() {
+                let (ray, rec) = hit(&glass, &direction(30.0), front_face);
+                assert!(glass.sample(&ray, &rec, &mut rng).is_none());
+                assert_eq!(glass.pdf(&ray, &rec, &-direction(30.0)), 0.0);
+            }
+        }
+    }
+}
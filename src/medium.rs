@@ -1,5 +1,5 @@
-use rand::{self,Rng};
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 use super::hittable::{Hittable, HitRecord};
 use super::material::Material;
 use super::vec3::Vec3;
@@ -7,13 +7,13 @@ use super::ray::Ray;
 use super::bbox::AABB;
 
 pub struct ConstantMedium {
-    boundary: Rc<dyn Hittable>,
-    phase_function: Rc<dyn Material>,
+    boundary: Arc<dyn Hittable>,
+    phase_function: Arc<dyn Material>,
     neg_inv_density: f64,
 }
 
 impl ConstantMedium {
-    pub fn new(boundary: Rc<dyn Hittable>, phase_function: Rc<dyn Material>, density: f64) -> Self {
+    pub fn new(boundary: Arc<dyn Hittable>, phase_function: Arc<dyn Material>, density: f64) -> Self {
         Self {
             boundary, phase_function,
             neg_inv_density: -1.0 / density,
@@ -22,19 +22,9 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let rec1 = self.boundary.hit(ray, std::f64::NEG_INFINITY, std::f64::INFINITY);
-        if rec1.is_none() {
-            return None;
-        }
-
-        let mut rec1 = rec1.unwrap();
-        let rec2 = self.boundary.hit(ray, rec1.t + 0.0001, std::f64::INFINITY);
-        if rec2.is_none() {
-            return None;
-        }
-
-        let mut rec2 = rec2.unwrap();
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut rec1 = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY, rng)?;
+        let mut rec2 = self.boundary.hit(ray, rec1.t + 0.0001, f64::INFINITY, rng)?;
         if rec1.t < t_min {
             rec1.t = t_min;
         }
@@ -50,15 +40,16 @@ impl Hittable for ConstantMedium {
             rec1.t = 0.0;
         }
 
-        let distance_inside_boundary = rec2.t - rec1.t; // * ray.dir().length();
-        let mut rng = rand::thread_rng();
-        let random: f64 = rng.gen_range(-1.0..1.0);
-        let hit_distance = self.neg_inv_density * random.log2();
+        let ray_length = ray.dir().length();
+        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+        // Exponentially distributed free flight distance, 1 - gen() is in (0, 1] so ln is finite
+        let random: f64 = 1.0 - rng.gen::<f64>();
+        let hit_distance = self.neg_inv_density * random.ln();
         if hit_distance > distance_inside_boundary {
             return None;
         }
 
-        let t = hit_distance; // / ray.dir().length();
+        let t = rec1.t + hit_distance / ray_length;
         let p = ray.at(t);
         let n = Vec3::new(1.0, 0.0, 0.0); // arbitrary
         
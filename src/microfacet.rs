@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+
+use super::vec3::Vec3;
+
+// Below this alpha a surface is treated as perfectly smooth, the distribution is too peaked to
+// evaluate and is sampled as a mirror instead
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+// Isotropic GGX (Trowbridge-Reitz) distribution of microfacet normals with Smith's height
+// correlated masking-shadowing. Directions are in the local frame of the surface, where the
+// macro normal is +z and both directions are on its side.
+#[derive(Copy, Clone, Debug)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+#[allow(dead_code)]
+impl Ggx {
+    // Perceptual roughness in [0, 1], squared into alpha so that it looks roughly linear
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    // Density of microfacet normals h, by projected area
+    pub fn d(&self, h: &Vec3) -> f64 {
+        let cos_2 = h.z() * h.z();
+        if cos_2 <= 0.0 {
+            return 0.0;
+        }
+        let alpha_2 = self.alpha * self.alpha;
+        let denom = cos_2 * (alpha_2 - 1.0) + 1.0;
+        alpha_2 / (PI * denom * denom)
+    }
+
+    // Smith's auxiliary function, the invisible projected area of microfacets over the visible
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos_2 = w.z() * w.z();
+        if cos_2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan_2 = (1.0 - cos_2).max(0.0) / cos_2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan_2).sqrt() - 1.0)
+    }
+
+    // Fraction of microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of microfacets visible from both wo and wi
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a microfacet normal from those visible from wo (Heitz, "Sampling the GGX
+    // Distribution of Visible Normals", 2018), wo must be above the surface
+    pub fn sample_visible_normal(&self, wo: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view so the distribution becomes a hemisphere
+        let vh = Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).unit_vector();
+        let length_2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if length_2 > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / length_2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // A point on the disk, squeezed onto the part of the hemisphere facing the view
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch
+        Vec3::new(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(1e-6)).unit_vector()
+    }
+
+    // Density, by solid angle, of sample_visible_normal returning h for wo
+    pub fn visible_normal_pdf(&self, wo: &Vec3, h: &Vec3) -> f64 {
+        let cos_o = wo.z();
+        let wo_dot_h = wo.dot(*h);
+        if cos_o <= 0.0 || wo_dot_h <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo_dot_h * self.d(h) / cos_o
+    }
+}
@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::vec3::{Point3, Vec3, Color};
+use super::material::{Material, Lambertian, Metal, Dielectric, DiffuseLight};
+use super::texture::{Texture, SolidTexture, ImageTexture};
+use super::triangle::TriangleMesh;
+
+// MTL materials by name, each with whether it emits light
+pub type MtlMaterials = HashMap<String, (Arc<dyn Material>, bool)>;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io {path, error} => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {path, line, message} => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io {error, ..} => Some(error),
+            ObjError::Parse {..} => None,
+        }
+    }
+}
+
+// Loads a Wavefront OBJ file as one triangle mesh per material, each with whether its material
+// emits light, so that emissive meshes can be sampled as lights. Faces before any usemtl statement
+// use default_material, which emits light if default_emissive is set. Polygons are triangulated as
+// fans around their first vertex.
+pub fn load_obj<P: AsRef<Path>>(path: P, default_material: Arc<dyn Material>, default_emissive: bool)
+        -> Result<Vec<(TriangleMesh, bool)>, ObjError> {
+    let path = path.as_ref();
+    parse_obj(path, &read_file(path)?, default_material, default_emissive)
+}
+
+// Parses the OBJ source read from path, which mtllib statements are relative to
+fn parse_obj(path: &Path, source: &str, default_material: Arc<dyn Material>, default_emissive: bool)
+        -> Result<Vec<(TriangleMesh, bool)>, ObjError> {
+    let mut positions = Vec::<Point3>::new();
+    let mut uvs = Vec::<(f64, f64)>::new();
+    let mut normals = Vec::<Vec3>::new();
+    let mut materials = MtlMaterials::new();
+    // Faces grouped by the material they use, in the order the materials first appear
+    let mut groups = vec![MeshBuilder::new(default_material, default_emissive)];
+    let mut group_by_name = HashMap::<String, usize>::new();
+    let mut current = 0;
+
+    for (line, text) in source.lines().enumerate() {
+        let mut parser = LineParser::new(path, line + 1, text);
+        let keyword = match parser.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(parser.vec3()?),
+            "vt" => {
+                let u = parser.f64()?;
+                let v = parser.optional_f64()?.unwrap_or(0.0);
+                uvs.push((u, v));
+            },
+            "vn" => normals.push(parser.vec3()?),
+            "f" => {
+                let mut corners = Vec::new();
+                while let Some(corner) = parser.next() {
+                    corners.push(parser.face_corner(corner, positions.len(), uvs.len(), normals.len())?);
+                }
+                if corners.len() < 3 {
+                    return Err(parser.error(format!("face has {} vertices, at least 3 are needed", corners.len())));
+                }
+                groups[current].add_polygon(&corners);
+            },
+            "mtllib" => {
+                let file = parser.rest().ok_or_else(|| parser.error("mtllib needs a file name".to_string()))?;
+                let mtl_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+                materials.extend(load_mtl(&mtl_path)?);
+            },
+            "usemtl" => {
+                let name = parser.rest().ok_or_else(|| parser.error("usemtl needs a material name".to_string()))?;
+                current = match group_by_name.get(name) {
+                    Some(&index) => index,
+                    None => {
+                        let (material, emissive) = materials.get(name)
+                            .ok_or_else(|| parser.error(format!("unknown material {}", name)))?;
+                        groups.push(MeshBuilder::new(material.clone(), *emissive));
+                        group_by_name.insert(name.to_string(), groups.len() - 1);
+                        groups.len() - 1
+                    },
+                };
+            },
+            // Grouping, smoothing and other statements don't affect the geometry we build
+            _ => {},
+        }
+    }
+
+    Ok(groups.into_iter()
+        .filter(|g| !g.indices.is_empty())
+        .map(|g| {
+            let emissive = g.emissive;
+            (g.build(&positions, &uvs, &normals), emissive)
+        })
+        .collect())
+}
+
+// Loads the materials of a Wavefront MTL file, each with whether it emits light. Kd, map_Kd, Ks,
+// Ns, Ni, Ke, d, Tr and illum are mapped onto the closest existing material:
+// - any non black Ke is a DiffuseLight
+// - transparent materials (d < 1, Tr > 0 or illum 4, 6, 7, 9) are Dielectric with index Ni
+// - a non black Ks with illum 3 or a black Kd is a Metal, fuzzed according to Ns
+// - everything else is Lambertian with Kd, or the map_Kd texture, which must load
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<MtlMaterials, ObjError> {
+    let path = path.as_ref();
+    parse_mtl(path, &read_file(path)?)
+}
+
+// Parses the MTL source read from path, which texture maps are relative to
+fn parse_mtl(path: &Path, source: &str) -> Result<MtlMaterials, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlDefinition)> = None;
+    for (line, text) in source.lines().enumerate() {
+        let mut parser = LineParser::new(path, line + 1, text);
+        let keyword = match parser.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = parser.rest().ok_or_else(|| parser.error("newmtl needs a material name".to_string()))?;
+            if let Some((name, definition)) = current.take() {
+                materials.insert(name, (definition.build(path)?, definition.emissive()));
+            }
+            current = Some((name.to_string(), MtlDefinition::default()));
+            continue;
+        }
+
+        let definition = match current {
+            Some((_, ref mut definition)) => definition,
+            None => return Err(parser.error(format!("{} before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => definition.kd = parser.vec3()?,
+            "Ks" => definition.ks = parser.vec3()?,
+            "Ke" => definition.ke = parser.vec3()?,
+            "Ns" => definition.ns = parser.f64()?,
+            "Ni" => definition.ni = parser.f64()?,
+            "d" => definition.dissolve = parser.f64()?,
+            "Tr" => definition.dissolve = 1.0 - parser.f64()?,
+            "illum" => definition.illum = parser.f64()? as i32,
+            "map_Kd" => {
+                // Options such as -s or -o come before the file name, which is the last argument
+                let file = parser.rest().and_then(|r| r.split_whitespace().last())
+                    .ok_or_else(|| parser.error("map_Kd needs a file name".to_string()))?;
+                definition.map_kd = Some((file.to_string(), parser.line));
+            },
+            _ => {},
+        }
+    }
+
+    if let Some((name, definition)) = current.take() {
+        materials.insert(name, (definition.build(path)?, definition.emissive()));
+    }
+    Ok(materials)
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {path: path.to_path_buf(), error})
+}
+
+struct MtlDefinition {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ns: f64,
+    ni: f64,
+    dissolve: f64,
+    illum: i32,
+    // Texture file and the line naming it
+    map_kd: Option<(String, usize)>,
+}
+
+impl Default for MtlDefinition {
+    fn default() -> Self {
+        Self {
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::default(),
+            ke: Color::default(),
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlDefinition {
+    fn emissive(&self) -> bool {
+        !self.ke.near_zero()
+    }
+
+    fn build(&self, mtl_path: &Path) -> Result<Arc<dyn Material>, ObjError> {
+        if self.emissive() {
+            return Ok(Arc::new(DiffuseLight::new(Arc::new(SolidTexture::new(&self.ke)))));
+        }
+
+        let transparent = self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9);
+        if transparent {
+            return Ok(Arc::new(Dielectric::new(self.ni)));
+        }
+
+        if !self.ks.near_zero() && (self.illum == 3 || self.kd.near_zero()) {
+            // Phong exponent to a roughness like fuzz, sharp highlights have large exponents
+            let fuzz = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt();
+            return Ok(Arc::new(Metal::new(Arc::new(SolidTexture::new(&self.ks)), fuzz)));
+        }
+
+        let albedo: Arc<dyn Texture> = match self.map_kd {
+            Some((ref file, line)) => {
+                let texture_path = mtl_path.parent().unwrap_or_else(|| Path::new("")).join(file);
+                let texture = ImageTexture::open(&texture_path.to_string_lossy()).map_err(|e| ObjError::Parse {
+                    path: mtl_path.to_path_buf(),
+                    line,
+                    message: format!("failed to load image {}: {}", texture_path.display(), e),
+                })?;
+                Arc::new(texture)
+            },
+            None => Arc::new(SolidTexture::new(&self.kd)),
+        };
+        Ok(Arc::new(Lambertian::new(albedo)))
+    }
+}
+
+// Zero based position, uv and normal index of a face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct MeshBuilder {
+    material: Arc<dyn Material>,
+    emissive: bool,
+    corners: Vec<Corner>,
+    corner_by_indices: HashMap<Corner, usize>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material>, emissive: bool) -> Self {
+        Self {
+            material, emissive,
+            corners: Vec::new(),
+            corner_by_indices: HashMap::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn add_polygon(&mut self, corners: &[Corner]) {
+        let vertices: Vec<usize> = corners.iter().map(|c| self.vertex(c)).collect();
+        for i in 1..vertices.len() - 1 {
+            self.indices.push([vertices[0], vertices[i], vertices[i + 1]]);
+        }
+    }
+
+    // Corners sharing all of their indices share a mesh vertex
+    fn vertex(&mut self, corner: &Corner) -> usize {
+        let corners = &mut self.corners;
+        *self.corner_by_indices.entry(*corner).or_insert_with(|| {
+            corners.push(*corner);
+            corners.len() - 1
+        })
+    }
+
+    // A mesh only gets uvs or normals when every one of its corners has them
+    fn build(self, positions: &[Point3], uvs: &[(f64, f64)], normals: &[Vec3]) -> TriangleMesh {
+        let has_uvs = self.corners.iter().all(|c| c.1.is_some());
+        let has_normals = self.corners.iter().all(|c| c.2.is_some());
+
+        let mesh_positions = self.corners.iter().map(|c| positions[c.0]).collect();
+        let mesh_uvs = if has_uvs {
+            self.corners.iter().map(|c| uvs[c.1.unwrap()]).collect()
+        } else {
+            Vec::new()
+        };
+        let mesh_normals = if has_normals {
+            self.corners.iter().map(|c| normals[c.2.unwrap()]).collect()
+        } else {
+            Vec::new()
+        };
+        TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, self.indices, self.material)
+    }
+}
+
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+    rest: &'a str,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(path: &'a Path, line: usize, text: &'a str) -> Self {
+        // Everything after # is a comment
+        let text = match text.find('#') {
+            Some(index) => &text[..index],
+            None => text,
+        };
+        Self {
+            path, line,
+            rest: text.trim(),
+        }
+    }
+
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+
+    // Returns the next whitespace separated token
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(&rest[..end])
+    }
+
+    // Returns the remainder of the line, for names that may contain spaces
+    fn rest(&mut self) -> Option<&'a str> {
+        let rest = self.rest.trim();
+        self.rest = "";
+        if rest.is_empty() {None} else {Some(rest)}
+    }
+
+    fn optional_f64(&mut self) -> Result<Option<f64>, ObjError> {
+        match self.next() {
+            Some(token) => token.parse::<f64>()
+                .map(Some)
+                .map_err(|_| self.error(format!("expected a number, found {}", token))),
+            None => Ok(None),
+        }
+    }
+
+    fn f64(&mut self) -> Result<f64, ObjError> {
+        self.optional_f64()?.ok_or_else(|| self.error("expected a number".to_string()))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    // Parses v, v/vt, v//vn or v/vt/vn with 1 based or negative, relative indices
+    fn face_corner(&self, token: &str, positions: usize, uvs: usize, normals: usize) -> Result<Corner, ObjError> {
+        let mut parts = token.split('/');
+        let position = match parts.next() {
+            Some(p) if !p.is_empty() => self.index(p, positions, "position")?,
+            _ => return Err(self.error(format!("face vertex {} has no position index", token))),
+        };
+        let uv = match parts.next() {
+            Some(p) if !p.is_empty() => Some(self.index(p, uvs, "texture coordinate")?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(p) if !p.is_empty() => Some(self.index(p, normals, "normal")?),
+            _ => None,
+        };
+        if parts.next().is_some() {
+            return Err(self.error(format!("face vertex {} has too many indices", token)));
+        }
+        Ok((position, uv, normal))
+    }
+
+    fn index(&self, token: &str, count: usize, kind: &str) -> Result<usize, ObjError> {
+        let index = token.parse::<i64>()
+            .map_err(|_| self.error(format!("invalid {} index {}", kind, token)))?;
+        let resolved = if index > 0 {
+            index - 1
+        } else {
+            count as i64 + index
+        };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of range, {} defined so far", kind, index, count)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::hittable::HitRecord;
+    use crate::ray::Ray;
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.5, 0.5, 0.5)))))
+    }
+
+    fn parse(source: &str) -> Result<Vec<(TriangleMesh, bool)>, ObjError> {
+        parse_obj(Path::new("test.obj"), source, gray(), false)
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_latest_vertex() {
+        let meshes = parse("v 0 0 0\nv 2 0 0\nv 0 2 0\nv 0 0 4\nf -1 -2 -3\n").unwrap();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].0.len(), 1);
+        // The triangle through the last three vertices, not the first three with area 2
+        assert!((meshes[0].0.area() - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let meshes = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(meshes[0].0.len(), 2);
+        assert!((meshes[0].0.area() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zero_index_is_an_error_on_its_line() {
+        match parse("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 0 1 2\n") {
+            Err(ObjError::Parse {line, ..}) => assert_eq!(line, 5),
+            other => panic!("expected a parse error, got {:?}", other.map(|m| m.len())),
+        }
+    }
+
+    #[test]
+    fn out_of_range_index_is_an_error_on_its_line() {
+        match parse("v 0 0 0\nv 1 0 0\nf 1 2 3\nv 0 1 0\n") {
+            Err(ObjError::Parse {line, ..}) => assert_eq!(line, 3),
+            other => panic!("expected a parse error, got {:?}", other.map(|m| m.len())),
+        }
+    }
+
+    #[test]
+    fn emission_makes_an_emissive_material() {
+        let materials = parse_mtl(Path::new("test.mtl"), "newmtl lamp\nKe 4 4 4\n\nnewmtl wall\nKd 0.5 0.5 0.5\n").unwrap();
+        let p = Point3::default();
+
+        let (lamp, lamp_emissive) = &materials["lamp"];
+        assert!(lamp_emissive);
+        assert_eq!(lamp.emitted(0.0, 0.0, &p).y(), 4.0);
+
+        let (wall, wall_emissive) = &materials["wall"];
+        assert!(!wall_emissive);
+        assert!(wall.emitted(0.0, 0.0, &p).near_zero());
+    }
+
+    #[test]
+    fn faces_without_usemtl_take_the_default_emission() {
+        let meshes = parse_obj(Path::new("test.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n", gray(), true).unwrap();
+        assert!(meshes[0].1);
+    }
+    // Writes a file into a directory of this test run, for the loaders that read from disk
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-tests-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // A ray coming down at 45 degrees onto the y = 0 plane at the origin
+    fn hit(material: &Arc<dyn Material>) -> (Ray, HitRecord) {
+        let ray = Ray::new(&Point3::new(-1.0, 1.0, 0.0), &Vec3::new(1.0, -1.0, 0.0).unit_vector(), 0.0);
+        let rec = HitRecord::new(&ray, 2.0f64.sqrt(), &Point3::default(), &Vec3::new(0.0, 1.0, 0.0), material.clone(), 0.0, 0.0);
+        (ray, rec)
+    }
+
+    #[test]
+    fn mtl_statements_pick_the_closest_material() {
+        let path = write_temp("mapping.mtl", "\
+newmtl matte
+Kd 0.2 0.4 0.6
+
+# A weak highlight on a diffuse base stays diffuse unless illum asks for reflection
+newmtl plastic
+Kd 0.2 0.4 0.6
+Ks 0.5 0.5 0.5
+illum 2
+
+newmtl mirror
+Kd 0.2 0.4 0.6
+Ks 0.9 0.8 0.7
+Ns 100000
+illum 3
+
+newmtl glass
+Ni 1.5
+illum 7
+");
+        let materials = load_mtl(&path).unwrap();
+        assert_eq!(materials.len(), 4);
+        assert!(materials.values().all(|(_, emissive)| !emissive));
+        let mut rng = StdRng::seed_from_u64(1);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+
+        // Lambertian with Kd
+        for name in ["matte", "plastic"].iter() {
+            let material = &materials[*name].0;
+            let (ray, rec) = hit(material);
+            let scatter = material.sample(&ray, &rec, &mut rng).unwrap();
+            assert!(!scatter.specular);
+            assert!((scatter.attenuation - Color::new(0.2, 0.4, 0.6)).near_zero());
+            let f = material.eval(&ray, &rec, &up);
+            assert!((f.y() - 0.4 * std::f64::consts::FRAC_1_PI).abs() < 1e-12, "{} is not Lambertian", name);
+        }
+
+        // Metal with Ks, and a fuzz of almost nothing for the large Ns
+        let mirror = &materials["mirror"].0;
+        let (ray, rec) = hit(mirror);
+        let scatter = mirror.sample(&ray, &rec, &mut rng).unwrap();
+        assert!(scatter.specular);
+        assert!((scatter.attenuation - Color::new(0.9, 0.8, 0.7)).near_zero());
+        assert!((scatter.ray.dir() - Vec3::new(1.0, 1.0, 0.0).unit_vector()).length() < 0.05);
+
+        // Dielectric with index Ni, every ray either reflects or refracts by Snell's law
+        let glass = &materials["glass"].0;
+        let (ray, rec) = hit(glass);
+        let mut refracted = 0;
+        for _ in 0..100 {
+            let scatter = glass.sample(&ray, &rec, &mut rng).unwrap();
+            assert!(scatter.specular);
+            assert!((scatter.attenuation - Color::new(1.0, 1.0, 1.0)).near_zero());
+            let dir = scatter.ray.dir().unit_vector();
+            if dir.y() < 0.0 {
+                refracted += 1;
+                assert!((dir.x() - 0.5f64.sqrt() / 1.5).abs() < 1e-9);
+            } else {
+                assert!((dir - Vec3::new(1.0, 1.0, 0.0).unit_vector()).length() < 1e-9);
+            }
+        }
+        assert!(refracted > 80, "{} of 100 rays refracted", refracted);
+    }
+
+    #[test]
+    fn missing_texture_map_is_an_error_on_its_line() {
+        let path = write_temp("missing_map.mtl", "newmtl wood\nKd 1 1 1\nmap_Kd -s 2 2 1 missing_wood.png\n\nnewmtl other\n");
+        match load_mtl(&path) {
+            Err(ObjError::Parse {path: error_path, line, message}) => {
+                assert_eq!(error_path, path);
+                assert_eq!(line, 3);
+                assert!(message.contains("missing_wood.png"), "{}", message);
+            },
+            other => panic!("expected a parse error, got {:?}", other.map(|m| m.len())),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use super::vec3::Vec3;
+
+// Orthonormal basis built around a single direction, which becomes the w axis
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+#[allow(dead_code)]
+impl Onb {
+    pub fn new(w: &Vec3) -> Self {
+        let w = w.unit_vector();
+        let a = if w.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        Self {
+            axis: [u, v, w],
+        }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    // Converts a vector from basis coordinates to world coordinates
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.axis[0] * a.x() + self.axis[1] * a.y() + self.axis[2] * a.z()
+    }
+
+    // Converts a vector from world coordinates to basis coordinates, the inverse of local
+    pub fn to_basis(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.axis[0]), a.dot(self.axis[1]), a.dot(self.axis[2]))
+    }
+}
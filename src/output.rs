@@ -0,0 +1,367 @@
+use image::{self, ImageResult, ColorType, ImageFormat, Rgb};
+use image::codecs::hdr::HdrEncoder;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::vec3::Color;
+use super::framebuffer::Framebuffer;
+use super::tonemap::linear_to_srgb;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    // Binary P6 PPM
+    Ppm,
+    // ASCII P3 PPM
+    PpmAscii,
+    // Radiance RGBE, linear and unclamped
+    Hdr,
+    // Portable float map, linear and unclamped
+    Pfm,
+    // Uncompressed OpenEXR with 32 bit float channels, linear and unclamped
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "ppm" => Some(OutputFormat::Ppm),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None,
+        }
+    }
+
+    // Picks the format from the file extension, falling back to ASCII PPM
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        path.as_ref().extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(OutputFormat::from_extension)
+            .unwrap_or(OutputFormat::PpmAscii)
+    }
+
+    // HDR formats store linear radiance, the others expect a tone mapped image
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputFormat::Hdr | OutputFormat::Pfm | OutputFormat::Exr)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpg" | "jpeg" => Ok(OutputFormat::Jpeg),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "ppm" | "p6" => Ok(OutputFormat::Ppm),
+            "ppm-ascii" | "p3" => Ok(OutputFormat::PpmAscii),
+            "hdr" => Ok(OutputFormat::Hdr),
+            "pfm" => Ok(OutputFormat::Pfm),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(format!("Unknown output format {}", s)),
+        }
+    }
+}
+
+pub fn save<P: AsRef<Path>>(image: &Framebuffer, path: P, format: OutputFormat) -> ImageResult<()> {
+    let image_format = match format {
+        OutputFormat::Png => ImageFormat::Png,
+        OutputFormat::Jpeg => ImageFormat::Jpeg,
+        OutputFormat::Bmp => ImageFormat::Bmp,
+        OutputFormat::Ppm => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_ppm_binary(image, &mut out)?;
+            return Ok(out.flush()?);
+        },
+        OutputFormat::PpmAscii => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_ppm(image, &mut out)?;
+            return Ok(out.flush()?);
+        },
+        OutputFormat::Hdr => {
+            let out = BufWriter::new(File::create(path)?);
+            return write_hdr(image, out);
+        },
+        OutputFormat::Pfm => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_pfm(image, &mut out)?;
+            return Ok(out.flush()?);
+        },
+        OutputFormat::Exr => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_exr(image, &mut out)?;
+            return Ok(out.flush()?);
+        },
+    };
+
+    let buffer: Vec<u8> = image.pixels().iter().flat_map(to_rgb8).collect();
+    image::save_buffer_with_format(path, &buffer,
+                                image.width() as u32, image.height() as u32,
+                                ColorType::Rgb8, image_format)
+}
+
+// Writes the image as ASCII P3 PPM
+pub fn write_ppm<W: Write>(image: &Framebuffer, out: &mut W) -> io::Result<()> {
+    // Image width height
+    out.write_fmt(format_args!("P3 {} {}\n", image.width(), image.height()))?;
+    // 255 for max color
+    out.write_fmt(format_args!("{}\n", 255))?;
+    // RGB triplets
+    for color in image.pixels() {
+        let [r, g, b] = to_rgb8(color);
+        out.write_fmt(format_args!("{} {} {}\n", r, g, b))?;
+    }
+    Ok(())
+}
+
+// Writes the image as binary P6 PPM
+pub fn write_ppm_binary<W: Write>(image: &Framebuffer, out: &mut W) -> io::Result<()> {
+    out.write_fmt(format_args!("P6 {} {}\n{}\n", image.width(), image.height(), 255))?;
+    for color in image.pixels() {
+        out.write_all(&to_rgb8(color))?;
+    }
+    Ok(())
+}
+
+// Writes the image as Radiance RGBE, negative values are clamped to zero
+pub fn write_hdr<W: Write>(image: &Framebuffer, out: W) -> ImageResult<()> {
+    let data: Vec<Rgb<f32>> = image.pixels().iter()
+        .map(|c| Rgb([c.x().max(0.0) as f32, c.y().max(0.0) as f32, c.z().max(0.0) as f32]))
+        .collect();
+    HdrEncoder::new(out).encode(&data, image.width(), image.height())
+}
+
+// Writes the image as a little endian portable float map
+pub fn write_pfm<W: Write>(image: &Framebuffer, out: &mut W) -> io::Result<()> {
+    // A negative scale marks little endian data
+    out.write_fmt(format_args!("PF\n{} {}\n-1.0\n", image.width(), image.height()))?;
+    // PFM stores the bottom row first
+    for y in (0..image.height()).rev() {
+        for color in image.row(y) {
+            for i in 0..3 {
+                out.write_all(&(color[i] as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes the image as a single part, uncompressed scanline OpenEXR file with
+// 32 bit float B, G and R channels
+pub fn write_exr<W: Write>(image: &Framebuffer, out: &mut W) -> io::Result<()> {
+    const CHANNELS: [&str; 3] = ["B", "G", "R"];
+    const PIXEL_TYPE_FLOAT: i32 = 2;
+
+    let width = image.width();
+    let height = image.height();
+
+    let mut header = Vec::new();
+    // Magic number and version 2, single part scanline
+    header.extend_from_slice(&20000630i32.to_le_bytes());
+    header.extend_from_slice(&2i32.to_le_bytes());
+
+    let mut channels = Vec::new();
+    for name in CHANNELS.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved bytes
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    exr_attribute(&mut header, "channels", "chlist", &channels);
+    // No compression
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    // Every scanline is its own chunk: y, data size and the channel data
+    let line_size = width * CHANNELS.len() * 4;
+    let chunk_size = 8 + line_size;
+    let first_chunk = header.len() + height * 8;
+    out.write_all(&header)?;
+    for y in 0..height {
+        out.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(chunk_size);
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        // Channels are stored one after another, in the order B, G, R
+        for i in (0..3).rev() {
+            for color in image.row(y) {
+                line.extend_from_slice(&(color[i] as f32).to_le_bytes());
+            }
+        }
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Applies the sRGB transfer curve to a linear color and quantizes it to 8 bits per channel
+fn to_rgb8(color: &Color) -> [u8; 3] {
+    let r = (255.999 * linear_to_srgb(color.x().clamp(0.0, 1.0))) as u8;
+    let g = (255.999 * linear_to_srgb(color.y().clamp(0.0, 1.0))) as u8;
+    let b = (255.999 * linear_to_srgb(color.z().clamp(0.0, 1.0))) as u8;
+    [r, g, b]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrDecoder;
+    use std::convert::TryInto;
+
+    // 3 by 2 pixels that all differ, with a negative and a large value the HDR formats keep
+    fn image() -> Framebuffer {
+        let mut image = Framebuffer::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                let i = (y * 3 + x) as f64;
+                image.set(x, y, &Color::new(i + 0.25, 10.0 * i + 0.5, if i == 4.0 { -1.0 } else { 100.0 * i + 0.75 }));
+            }
+        }
+        image
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    fn c_string(bytes: &[u8], offset: usize) -> (&str, usize) {
+        let end = offset + bytes[offset..].iter().position(|&b| b == 0).unwrap();
+        (std::str::from_utf8(&bytes[offset..end]).unwrap(), end + 1)
+    }
+
+    #[test]
+    fn pfm_is_little_endian_bottom_row_first() {
+        let image = image();
+        let mut out = Vec::new();
+        write_pfm(&image, &mut out).unwrap();
+
+        let header = "PF\n3 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header.as_bytes());
+        let data = &out[header.len()..];
+        assert_eq!(data.len(), 3 * 2 * 3 * 4);
+        for (row, y) in [1, 0].iter().copied().enumerate() {
+            for x in 0..3 {
+                for i in 0..3 {
+                    let offset = ((row * 3 + x) * 3 + i) * 4;
+                    assert_eq!(f32_at(data, offset), image.get(x, y)[i] as f32, "pixel ({}, {}) channel {}", x, y, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exr_has_bgr_channels_and_an_offset_per_scanline() {
+        let image = image();
+        let mut out = Vec::new();
+        write_exr(&image, &mut out).unwrap();
+
+        assert_eq!(i32_at(&out, 0), 20000630);
+        assert_eq!(i32_at(&out, 4), 2);
+
+        // Attributes up to the empty name that ends the header
+        let mut offset = 8;
+        let mut channels = Vec::new();
+        loop {
+            let (name, next) = c_string(&out, offset);
+            if name.is_empty() {
+                offset = next;
+                break;
+            }
+            let (_, next) = c_string(&out, next);
+            let size = i32_at(&out, next) as usize;
+            let value = &out[next + 4..next + 4 + size];
+            if name == "channels" {
+                let mut at = 0;
+                while value[at] != 0 {
+                    let (channel, next) = c_string(value, at);
+                    assert_eq!(i32_at(value, next), 2, "channel {} is not 32 bit float", channel);
+                    channels.push(channel.to_string());
+                    at = next + 16;
+                }
+            }
+            offset = next + 4 + size;
+        }
+        assert_eq!(channels, ["B", "G", "R"]);
+
+        // The offset table points at one chunk per scanline, in order and back to back
+        let line_size = 3 * 3 * 4;
+        let first_chunk = offset + 2 * 8;
+        for y in 0..2 {
+            let entry = offset + y * 8;
+            let chunk = u64::from_le_bytes(out[entry..entry + 8].try_into().unwrap()) as usize;
+            assert_eq!(chunk, first_chunk + y * (8 + line_size));
+            assert_eq!(i32_at(&out, chunk), y as i32);
+            assert_eq!(i32_at(&out, chunk + 4), line_size as i32);
+            for (c, i) in [2, 1, 0].iter().copied().enumerate() {
+                for x in 0..3 {
+                    let value = f32_at(&out, chunk + 8 + (c * 3 + x) * 4);
+                    assert_eq!(value, image.get(x, y)[i] as f32, "pixel ({}, {}) channel {}", x, y, channels[c]);
+                }
+            }
+        }
+        assert_eq!(out.len(), first_chunk + 2 * (8 + line_size));
+    }
+
+    #[test]
+    fn hdr_round_trips_within_rgbe_precision() {
+        let image = image();
+        let mut out = Vec::new();
+        write_hdr(&image, &mut out).unwrap();
+
+        let decoder = HdrDecoder::new(&out[..]).unwrap();
+        let metadata = decoder.metadata();
+        assert_eq!((metadata.width, metadata.height), (3, 2));
+        let pixels = decoder.read_image_hdr().unwrap();
+        for (decoded, color) in pixels.iter().zip(image.pixels()) {
+            // RGBE shares an 8 bit exponent, every channel is within 1% of the largest
+            let largest = color.x().max(color.y()).max(color.z());
+            for i in 0..3 {
+                let expected = color[i].max(0.0);
+                assert!((decoded[i] as f64 - expected).abs() <= 0.01 * largest, "{} decoded as {}", expected, decoded[i]);
+            }
+        }
+    }
+}
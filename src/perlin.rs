@@ -0,0 +1,125 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use super::vec3::{Vec3, Point3};
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise, the same seed always builds the same lattice
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let length_squared = v.length_squared();
+                if length_squared > 1e-8 && length_squared <= 1.0 {
+                    break v.unit_vector();
+                }
+            })
+            .collect();
+
+        let perm_x = Perlin::generate_perm(&mut rng);
+        let perm_y = Perlin::generate_perm(&mut rng);
+        let perm_z = Perlin::generate_perm(&mut rng);
+        Self {
+            gradients,
+            perm_x, perm_y, perm_z,
+        }
+    }
+
+    // Noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[wrap(i + di as i64)]
+                        ^ self.perm_y[wrap(j + dj as i64)]
+                        ^ self.perm_z[wrap(k + dk as i64)];
+                    *gradient = self.gradients[index];
+                }
+            }
+        }
+
+        trilinear_interpolate(&c, u, v, w)
+    }
+
+    // Sum of octaves of absolute noise, in [0, 2)
+    pub fn turbulence(&self, p: &Point3, octaves: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(&p).abs();
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accum
+    }
+
+    // Fractional Brownian motion, octaves of signed noise doubling in frequency and halving in
+    // amplitude, normalized to roughly [-1, 1]
+    pub fn fbm(&self, p: &Point3, octaves: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut total_weight = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(&p);
+            total_weight += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        if total_weight > 0.0 {accum / total_weight} else {0.0}
+    }
+
+    fn generate_perm<R: Rng>(rng: &mut R) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.gen_range(0..=i);
+            perm.swap(i, target);
+        }
+        perm
+    }
+}
+
+fn wrap(i: i64) -> usize {
+    (i & (POINT_COUNT as i64 - 1)) as usize
+}
+
+fn trilinear_interpolate(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing removes the grid artifacts of plain linear interpolation
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+
+    let mut accum = 0.0;
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, gradient) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight = Vec3::new(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * gradient.dot(weight);
+            }
+        }
+    }
+    accum
+}
@@ -0,0 +1,57 @@
+use rand::RngCore;
+use std::sync::Arc;
+
+use super::vec3::{Point3, Vec3};
+use super::ray::Ray;
+use super::hittable::{Hittable, HitRecord};
+use super::material::Material;
+use super::onb::Onb;
+
+// An infinite plane through point, facing along normal. It has no bounding box, so a BVH keeps
+// it apart from the tree and tests it against every ray, and it can't be sampled as a light.
+pub struct Plane {
+    point: Point3,
+    basis: Onb,
+    material: Arc<dyn Material>,
+}
+
+#[allow(dead_code)]
+impl Plane {
+    pub fn new(point: &Point3, normal: &Vec3, material: Arc<dyn Material>) -> Self {
+        Self {
+            point: *point,
+            basis: Onb::new(normal),
+            material,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.basis.w()
+    }
+
+    // Texture coordinates repeat every unit of distance along the plane
+    pub fn uv(&self, p: &Point3) -> (f64, f64) {
+        let offset = *p - self.point;
+        (offset.dot(self.basis.u()).rem_euclid(1.0), offset.dot(self.basis.v()).rem_euclid(1.0))
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let normal = self.normal();
+        let denom = normal.dot(ray.dir());
+        // Rays parallel to the plane never meet it
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = normal.dot(self.point - ray.origin()) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let (u, v) = self.uv(&p);
+        Some(HitRecord::new(ray, t, &p, &normal, self.material.clone(), u, v))
+    }
+}
@@ -5,6 +5,8 @@ pub struct Ray {
     orig: Point3,
     dir: Vec3,
     t: f64,
+    // In nanometers, set once a path is traced at a single wavelength
+    wavelength: Option<f64>,
 }
 
 #[allow(dead_code)]
@@ -14,6 +16,14 @@ impl Ray {
             orig: *orig, 
             dir: *dir,
             t,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_wavelength(&self, wavelength: Option<f64>) -> Self {
+        Self {
+            wavelength,
+            ..*self
         }
     }
 
@@ -29,6 +39,10 @@ impl Ray {
         self.t
     }
 
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + self.dir * t
     }
@@ -1,4 +1,5 @@
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 use super::vec3::{Point3, Vec3};
 use super::material::Material;
 use super::hittable::{Hittable, HitRecord};
@@ -12,7 +13,7 @@ pub enum AARect {
         y0: f64,
         y1: f64,
         k: f64,
-        material: Rc<dyn Material>,
+        material: Arc<dyn Material>,
     },
     XZRect {
         x0: f64,
@@ -20,7 +21,7 @@ pub enum AARect {
         z0: f64,
         z1: f64,
         k: f64,
-        material: Rc<dyn Material>,
+        material: Arc<dyn Material>,
     },
     YZRect {
         y0: f64,
@@ -28,31 +29,43 @@ pub enum AARect {
         z0: f64,
         z1: f64,
         k: f64,
-        material: Rc<dyn Material>,
+        material: Arc<dyn Material>,
     }
 }
 
 impl AARect {
-    pub fn new_xy(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new_xy(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Arc<dyn Material>) -> Self {
         AARect::XYRect{x0, x1, y0, y1, k, material}
     }
 
-    pub fn new_xz(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new_xz(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
         AARect::XZRect{x0, x1, z0, z1, k, material}
     }
 
-    pub fn new_yz(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new_yz(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
         AARect::YZRect{y0, y1, z0, z1, k, material}
     }
-}
 
-impl Hittable for AARect {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let (d0, d1, d2) = match self {
+    // Returns (first in-plane axis, second in-plane axis, normal axis)
+    fn axes(&self) -> (usize, usize, usize) {
+        match self {
             AARect::XYRect{..} => (0, 1, 2),
             AARect::XZRect{..} => (0, 2, 1),
             AARect::YZRect{..} => (1, 2, 0)
-        };
+        }
+    }
+
+    // Returns (min0, max0, min1, max1, k) along the axes returned by axes()
+    fn bounds(&self) -> (f64, f64, f64, f64, f64) {
+        match self {
+            AARect::XYRect{x0, x1, y0, y1, k, ..} => (*x0, *x1, *y0, *y1, *k),
+            AARect::XZRect{x0, x1, z0, z1, k, ..} => (*x0, *x1, *z0, *z1, *k),
+            AARect::YZRect{y0, y1, z0, z1, k, ..} => (*y0, *y1, *z0, *z1, *k),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (d0, d1, d2) = self.axes();
 
         let (min0, max0, min1, max1, k, material) = match self {
             AARect::XYRect{x0, x1, y0, y1, k, material} => (x0, x1, y0, y1, k, material),
@@ -81,6 +94,13 @@ impl Hittable for AARect {
         let v = (v1 - min1) / (max1 - min1);
         Some(HitRecord::new(ray, t, &p, &n, material.clone(), u, v))
     }
+}
+
+impl Hittable for AARect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.intersect(ray, t_min, t_max)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         let (x0, y0, z0, x1, y1, z1) = match self {
             AARect::XYRect{x0, x1, y0, y1, k, ..} => (*x0, *y0, k - 0.0001, *x1, *y1, k + 0.0001),
@@ -91,4 +111,26 @@ impl Hittable for AARect {
         Some(AABB::new(&Point3::new(x0, y0, z0), 
                         &Point3::new(x1, y1, z1)))
     }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        if let Some(rec) = self.intersect(&Ray::new(origin, dir, 0.0), 0.0001, f64::MAX) {
+            let (min0, max0, min1, max1, _) = self.bounds();
+            let area = (max0 - min0) * (max1 - min1);
+            let distance_squared = rec.t * rec.t * dir.length_squared();
+            let cosine = (dir.dot(rec.normal) / dir.length()).abs();
+            distance_squared / (cosine * area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let (d0, d1, d2) = self.axes();
+        let (min0, max0, min1, max1, k) = self.bounds();
+        let mut p = Point3::default();
+        p[d0] = rng.gen_range(min0..max0);
+        p[d1] = rng.gen_range(min1..max1);
+        p[d2] = k;
+        p - *origin
+    }
 }
\ No newline at end of file
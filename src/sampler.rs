@@ -0,0 +1,479 @@
+use rand::RngCore;
+use serde::Deserialize;
+use std::str::FromStr;
+
+// Dimensions of a path sample. The camera takes the pixel position, then the lens position and
+// the time, and a path that reaches a dispersive material takes its wavelength. Every bounce
+// gets its own block, split between sampling the material and sampling the lights, so sample i
+// of a pixel always uses the same dimensions for the same decision.
+pub const PIXEL_DIMENSION: usize = 0;
+pub const CAMERA_DIMENSION: usize = 2;
+pub const CAMERA_DIMENSIONS: usize = 3;
+pub const WAVELENGTH_DIMENSION: usize = 5;
+pub const FIRST_BOUNCE_DIMENSION: usize = 8;
+pub const SCATTER_DIMENSIONS: usize = 4;
+pub const LIGHT_DIMENSIONS: usize = 4;
+pub const BOUNCE_DIMENSIONS: usize = SCATTER_DIMENSIONS + LIGHT_DIMENSIONS;
+
+// Halton dimensions past this many primes fall back to independent samples
+const HALTON_DIMENSIONS: usize = 1000;
+// Dimensions of the Sobol sequence, higher dimensions pad it with independently scrambled copies
+const SOBOL_DIMENSIONS: usize = 4;
+// Scrambled Halton digits are generated down to this resolution, the same as the 32 bit Sobol values
+const HALTON_RESOLUTION: f64 = 1.0 / 4294967296.0;
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Hands out the values of every dimension of every sample of every pixel
+pub trait Sampler: Send + Sync {
+    // Value in [0, 1) of a dimension of sample index of pixel (x, y), the same arguments
+    // always return the same value
+    fn sample(&self, x: usize, y: usize, index: usize, dimension: usize) -> f64;
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    // Uniform random values
+    #[default]
+    Independent,
+    // Jittered grid over pairs of dimensions
+    Stratified,
+    // Halton sequence with random digit scrambling
+    Halton,
+    // Sobol sequence with Owen scrambling, padded to any number of dimensions
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("Unknown sampler {}", s)),
+        }
+    }
+}
+
+impl SamplerKind {
+    // samples_per_pixel is the number of samples the stratified sampler divides its grid into
+    pub fn create(&self, seed: u64, samples_per_pixel: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {seed}
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn sample(&self, x: usize, y: usize, index: usize, dimension: usize) -> f64 {
+        to_unit(hash(self.seed, &[x as u64, y as u64, index as u64, dimension as u64]))
+    }
+}
+
+// Every pair of dimensions is a jittered grid of about samples_per_pixel cells, the cells are
+// visited in a different random order for every pair and pixel
+pub struct StratifiedSampler {
+    seed: u64,
+    x_strata: usize,
+    y_strata: usize,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: usize) -> Self {
+        let x_strata = ((samples_per_pixel as f64).sqrt() as usize).max(1);
+        let y_strata = samples_per_pixel.max(1).div_ceil(x_strata);
+        Self {
+            seed, x_strata, y_strata,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample(&self, x: usize, y: usize, index: usize, dimension: usize) -> f64 {
+        let cells = self.x_strata * self.y_strata;
+        let pair = (dimension / 2) as u64;
+        let permutation = hash(self.seed, &[x as u64, y as u64, pair]) as u32;
+        let cell = permutation_element((index % cells) as u32, cells as u32, permutation) as usize;
+        let jitter = to_unit(hash(self.seed, &[x as u64, y as u64, index as u64, dimension as u64]));
+        if dimension.is_multiple_of(2) {
+            ((cell % self.x_strata) as f64 + jitter) / self.x_strata as f64
+        } else {
+            ((cell / self.x_strata) as f64 + jitter) / self.y_strata as f64
+        }
+    }
+}
+
+// Dimension d uses the radical inverse in the d-th prime base. Every pixel scrambles the digits
+// with its own permutations, which also breaks up the correlation between high dimensions.
+pub struct HaltonSampler {
+    seed: u64,
+    primes: Vec<u64>,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().take_while(|&&p| p * p <= candidate).all(|&p| candidate % p != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        Self {
+            seed, primes,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn sample(&self, x: usize, y: usize, index: usize, dimension: usize) -> f64 {
+        let seed = hash(self.seed, &[x as u64, y as u64, dimension as u64]);
+        match self.primes.get(dimension) {
+            Some(&base) => scrambled_radical_inverse(base, index as u64, seed),
+            None => to_unit(hash(seed, &[index as u64])),
+        }
+    }
+}
+
+// Burley's practical hash based Owen scrambled Sobol sequence. The sample index is shuffled and
+// the values scrambled per pixel, and each group of SOBOL_DIMENSIONS dimensions is seeded apart.
+pub struct SobolSampler {
+    seed: u64,
+    directions: [[u32; 32]; SOBOL_DIMENSIONS],
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            directions: sobol_directions(),
+        }
+    }
+
+    fn sobol(&self, index: u32, dimension: usize) -> u32 {
+        let mut ret = 0;
+        let mut index = index;
+        while index != 0 {
+            ret ^= self.directions[dimension][index.trailing_zeros() as usize];
+            index &= index - 1;
+        }
+        ret
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn sample(&self, x: usize, y: usize, index: usize, dimension: usize) -> f64 {
+        let group = (dimension / SOBOL_DIMENSIONS) as u64;
+        let component = dimension % SOBOL_DIMENSIONS;
+        let seed = hash(self.seed, &[x as u64, y as u64, group]);
+        let shuffled = nested_uniform_scramble(index as u32, seed as u32);
+        let value = nested_uniform_scramble(self.sobol(shuffled, component), hash(seed, &[component as u64]) as u32);
+        (value as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+    }
+}
+
+// Sample index of a pixel, with access to all of its dimensions
+#[derive(Copy, Clone)]
+pub struct PixelSample<'a> {
+    sampler: &'a dyn Sampler,
+    x: usize,
+    y: usize,
+    index: usize,
+}
+
+impl<'a> PixelSample<'a> {
+    pub fn new(sampler: &'a dyn Sampler, x: usize, y: usize, index: usize) -> Self {
+        Self {
+            sampler, x, y, index,
+        }
+    }
+
+    pub fn get(&self, dimension: usize) -> f64 {
+        self.sampler.sample(self.x, self.y, self.index, dimension)
+    }
+
+    // A generator whose first count draws are the dimensions from first on, after that it draws
+    // from fallback, so code that samples with an RngCore can use the sampler unchanged
+    pub fn stream<'b>(&self, first: usize, count: usize, fallback: &'b mut dyn RngCore) -> SampleStream<'b>
+            where 'a: 'b {
+        SampleStream {
+            sample: *self,
+            next: first,
+            end: first + count,
+            fallback,
+        }
+    }
+}
+
+pub struct SampleStream<'a> {
+    sample: PixelSample<'a>,
+    next: usize,
+    end: usize,
+    fallback: &'a mut dyn RngCore,
+}
+
+impl RngCore for SampleStream<'_> {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Uniform floats and integer ranges are taken from the high bits, so they come out as the
+    // sample value scaled to the range
+    fn next_u64(&mut self) -> u64 {
+        if self.next < self.end {
+            let value = self.sample.get(self.next);
+            self.next += 1;
+            (value * 18446744073709551616.0) as u64
+        } else {
+            self.fallback.next_u64()
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// The splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// The finalizer maps 0 to 0, the offset keeps all zero inputs from hashing to 0
+fn hash(seed: u64, values: &[u64]) -> u64 {
+    values.iter().fold(mix(seed ^ 0x9e37_79b9_7f4a_7c15), |h, &v| mix(h ^ v.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+}
+
+fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Element i of a random permutation of 0..l chosen by p, from Kensler's "Correlated
+// Multi-Jittered Sampling"
+fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    // Offsetting by p % l instead of p keeps the sum from wrapping, which would map two elements
+    // to one whenever l does not divide 2^32
+    (i + p % l) % l
+}
+
+// Radical inverse of index in base with every digit position permuted its own way
+fn scrambled_radical_inverse(base: u64, index: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = inv_base;
+    let mut value = 0.0;
+    let mut index = index;
+    let mut digit_index = 0;
+    // Runs past the last nonzero digit, the permutations also move the trailing zeros
+    while inv_base_m * (base - 1) as f64 > HALTON_RESOLUTION {
+        let digit = (index % base) as u32;
+        let permuted = permutation_element(digit, base as u32, mix(seed ^ digit_index) as u32);
+        value += permuted as f64 * inv_base_m;
+        index /= base;
+        inv_base_m *= inv_base;
+        digit_index += 1;
+    }
+    value.min(ONE_MINUS_EPSILON)
+}
+
+// Laine and Karras' hash, with Vegdahl's constants, where every bit only depends on the
+// bits below it
+fn laine_karras_permutation(x: u32, seed: u32) -> u32 {
+    let mut x = x;
+    x ^= x.wrapping_mul(0x3d20_adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x0552_6c56);
+    x ^= x.wrapping_mul(0x53a2_2864);
+    x
+}
+
+// Owen scrambling of the bits of x, read as a fraction with the high bit first
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Direction numbers of the first Sobol dimensions, the first is the van der Corput sequence
+// and the others use the primitive polynomials and initial numbers of Joe and Kuo
+fn sobol_directions() -> [[u32; 32]; SOBOL_DIMENSIONS] {
+    // (degree, coefficients, initial direction numbers)
+    const PARAMETERS: [(usize, u32, [u32; 3]); SOBOL_DIMENSIONS - 1] = [
+        (1, 0, [1, 0, 0]),
+        (2, 1, [1, 3, 0]),
+        (3, 1, [1, 3, 1]),
+    ];
+
+    let mut directions = [[0u32; 32]; SOBOL_DIMENSIONS];
+    for (bit, direction) in directions[0].iter_mut().enumerate() {
+        *direction = 1 << (31 - bit);
+    }
+    for (dimension, &(degree, coefficients, initial)) in PARAMETERS.iter().enumerate() {
+        let v = &mut directions[dimension + 1];
+        for bit in 0..degree {
+            v[bit] = initial[bit] << (31 - bit);
+        }
+        for bit in degree..32 {
+            v[bit] = v[bit - degree] ^ (v[bit - degree] >> degree);
+            for k in 1..degree {
+                v[bit] ^= ((coefficients >> (degree - 1 - k)) & 1) * v[bit - k];
+            }
+        }
+    }
+    directions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+    const PIXELS: [(usize, usize); 3] = [(0, 0), (7, 3), (511, 1023)];
+
+    // Panics unless every one of the n intervals of length 1/n holds exactly one value
+    fn assert_one_per_interval(values: &[f64], n: usize) {
+        let mut counts = vec![0; n];
+        for &v in values {
+            counts[(v * n as f64) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&c| c == 1), "{} values over {} intervals: {:?}", values.len(), n, counts);
+    }
+
+    fn first_samples(sampler: &dyn Sampler, (x, y): (usize, usize), n: usize, dimension: usize) -> Vec<f64> {
+        (0..n).map(|index| sampler.sample(x, y, index, dimension)).collect()
+    }
+
+    #[test]
+    fn samples_are_in_unit_interval() {
+        // Past the Sobol dimensions and the Halton primes, where both fall back to other values
+        let dimensions = (0..64).chain(HALTON_DIMENSIONS - 2..HALTON_DIMENSIONS + 2);
+        for kind in KINDS {
+            for seed in 0..3 {
+                let sampler = kind.create(seed, 16);
+                for dimension in dimensions.clone() {
+                    for &(x, y) in PIXELS.iter() {
+                        for index in 0..256 {
+                            let v = sampler.sample(x, y, index, dimension);
+                            assert!((0.0..1.0).contains(&v), "{:?} dimension {} sample {} is {}", kind, dimension, index, v);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_first_samples_are_stratified() {
+        let sampler = SobolSampler::new(1);
+        for n in [2, 16, 64, 256] {
+            for &pixel in PIXELS.iter() {
+                assert_one_per_interval(&first_samples(&sampler, pixel, n, 0), n);
+                assert_one_per_interval(&first_samples(&sampler, pixel, n, 1), n);
+            }
+        }
+    }
+
+    #[test]
+    fn halton_first_samples_are_stratified() {
+        // Dimension 0 is in base 2 and dimension 1 in base 3, the scrambling keeps the strata
+        let sampler = HaltonSampler::new(1);
+        for &pixel in PIXELS.iter() {
+            for n in [2, 16, 64, 256] {
+                assert_one_per_interval(&first_samples(&sampler, pixel, n, 0), n);
+            }
+            for n in [3, 27, 243] {
+                assert_one_per_interval(&first_samples(&sampler, pixel, n, 1), n);
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_first_samples_fill_every_cell() {
+        // 16 samples make a 4 by 4 grid over each pair of dimensions, 10 make a 3 by 4 grid
+        // with two cells left over
+        for (samples_per_pixel, x_strata, y_strata) in [(16, 4, 4), (10, 3, 4)] {
+            let sampler = StratifiedSampler::new(1, samples_per_pixel);
+            let cells = x_strata * y_strata;
+            for &pixel in PIXELS.iter() {
+                for pair in [0, 2, 8] {
+                    let xs = first_samples(&sampler, pixel, cells, pair);
+                    let ys = first_samples(&sampler, pixel, cells, pair + 1);
+                    let mut counts = vec![0; cells];
+                    for (x, y) in xs.iter().zip(ys.iter()) {
+                        counts[(y * y_strata as f64) as usize * x_strata + (x * x_strata as f64) as usize] += 1;
+                    }
+                    assert!(counts.iter().all(|&c| c == 1), "dimensions {} and {}: {:?}", pair, pair + 1, counts);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn permutation_is_bijection() {
+        for l in 1..=70 {
+            for p in [0, 1, 0xdead_beef, u32::MAX] {
+                let mut seen = vec![false; l as usize];
+                for i in 0..l {
+                    let e = permutation_element(i, l, p);
+                    assert!(e < l && !seen[e as usize], "length {} seed {:#x} maps {} to {}", l, p, i, e);
+                    seen[e as usize] = true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use super::hittable::{Hittable, HittableList};
+use super::camera::Camera;
+use super::environment::{Environment, SolidBackground};
+
+pub struct Scene {
+    pub world: Arc<dyn Hittable>,
+    pub camera: Camera,
+    pub environment: Arc<dyn Environment>,
+    // Emissive objects, also in the world, that diffuse surfaces sample directly
+    pub lights: HittableList,
+}
+
+#[allow(dead_code)]
+impl Scene {
+    // A scene lit only by its emissive objects, with a black background
+    pub fn new(world: Arc<dyn Hittable>, camera: Camera) -> Self {
+        Self {
+            world, camera,
+            environment: Arc::new(SolidBackground::default()),
+            lights: HittableList::default(),
+        }
+    }
+
+    pub fn with_environment(world: Arc<dyn Hittable>, camera: Camera, environment: Arc<dyn Environment>) -> Self {
+        Self {
+            world, camera, environment,
+            lights: HittableList::default(),
+        }
+    }
+}
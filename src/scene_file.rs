@@ -0,0 +1,936 @@
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use toml::Spanned;
+
+use super::vec3::{Point3, Vec3, Color};
+use super::hittable::{Hittable, HittableList};
+use super::bvh::BVH;
+use super::camera::Camera;
+use super::scene::Scene;
+use super::environment::{Environment, SolidBackground, GradientSky, ImageEnvironment};
+use super::texture::{Texture, SolidTexture, CheckerTexture, ImageTexture, NoiseTexture, NoiseKind};
+use super::material::{Material, Lambertian, Metal, MetalPreset, Conductor, Dielectric, Dispersion, D_LINE_WAVELENGTH,
+                      Principled, PrincipledParameters, DiffuseLight, Isotropic};
+use super::sphere::{Sphere, AnimatedSphere};
+use super::rect::AARect;
+use super::plane::Plane;
+use super::sampler::SamplerKind;
+use super::filter::FilterKind;
+use super::aabox::AABox;
+use super::triangle::Triangle;
+use super::medium::ConstantMedium;
+use super::transform::{Matrix4, Transform};
+use super::obj::load_obj;
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneFileError::Io {path, error} => write!(f, "{}: {}", path.display(), error),
+            SceneFileError::Parse {path, line, column, message} => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            },
+        }
+    }
+}
+
+impl Error for SceneFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneFileError::Io {error, ..} => Some(error),
+            SceneFileError::Parse {..} => None,
+        }
+    }
+}
+
+// Render settings a scene file may carry, command line options take precedence over them
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderOptions {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub seed: Option<u64>,
+    // Enables adaptive sampling, samples is then the most a pixel takes
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples: Option<usize>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<f64>,
+}
+
+// A TOML scene description. Textures and materials are declared in named tables and
+// referenced by name, so several materials or objects can share one instance.
+pub struct SceneFile {
+    path: PathBuf,
+    source: String,
+    desc: SceneDesc,
+}
+
+#[allow(dead_code)]
+impl SceneFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneFileError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| SceneFileError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        SceneFile::parse(path, source)
+    }
+
+    // Parses source as if it was read from path, which relative paths in it are resolved against
+    pub fn parse<P: AsRef<Path>>(path: P, source: String) -> Result<Self, SceneFileError> {
+        let path = path.as_ref();
+        let desc = toml::from_str(&source).map_err(|e| {
+            located_error(path, &source, e.span().unwrap_or(0..0), e.message().to_string())
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            source, desc,
+        })
+    }
+
+    pub fn render_options(&self) -> &RenderOptions {
+        &self.desc.render
+    }
+
+    // Builds the scene, every bounded object with an emissive material is also sampled as a light
+    pub fn build(&self, aspect_ratio: f64) -> Result<Scene, SceneFileError> {
+        let mut builder = SceneBuilder {
+            file: self,
+            textures: HashMap::new(),
+            resolving: Vec::new(),
+            materials: HashMap::new(),
+        };
+
+        let mut textures: Vec<_> = self.desc.textures.iter().collect();
+        textures.sort_by_key(|(_, desc)| desc.span().start);
+        for (name, desc) in textures {
+            builder.texture(name, desc.span())?;
+        }
+
+        let mut materials: Vec<_> = self.desc.materials.iter().collect();
+        materials.sort_by_key(|(_, desc)| desc.span().start);
+        for (name, desc) in materials {
+            let material = builder.material(desc)?;
+            builder.materials.insert(name.clone(), material);
+        }
+
+        let mut objects = Vec::<Arc<dyn Hittable>>::new();
+        let mut lights = HittableList::default();
+        for desc in self.desc.objects.iter() {
+            let span = desc.span();
+            for (object, emissive) in builder.object(desc.get_ref(), &span, None)? {
+                // Unbounded objects have no area to sample, scattered rays still reach them
+                if emissive && object.bounding_box().is_some() {
+                    lights.add(object.clone());
+                }
+                objects.push(object);
+            }
+        }
+        let world: Arc<dyn Hittable> = Arc::new(BVH::new(objects));
+
+        let camera = self.desc.camera.get_ref().build(self, self.desc.camera.span(), aspect_ratio)?;
+        let mut scene = match self.desc.background {
+            Some(ref background) => Scene::with_environment(world, camera, builder.background(background)?),
+            None => Scene::new(world, camera),
+        };
+        scene.lights = lights;
+        Ok(scene)
+    }
+
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    fn error(&self, span: Range<usize>, message: String) -> SceneFileError {
+        located_error(&self.path, &self.source, span, message)
+    }
+}
+
+// Turns a byte range of the source into a 1-based line and column
+fn located_error(path: &Path, source: &str, span: Range<usize>, message: String) -> SceneFileError {
+    let before = &source[..span.start.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before[before.rfind('\n').map(|i| i + 1).unwrap_or(0)..].chars().count() + 1;
+    SceneFileError::Parse {
+        path: path.to_path_buf(),
+        line, column, message,
+    }
+}
+
+struct SceneBuilder<'a> {
+    file: &'a SceneFile,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    // Names of the textures being built, to report textures that reference themselves
+    resolving: Vec<String>,
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+// Built objects, each flagged when it should also be sampled as a light
+type BuiltObjects = Vec<(Arc<dyn Hittable>, bool)>;
+
+impl<'a> SceneBuilder<'a> {
+    // span locates the reference to the texture, for unknown names
+    fn texture(&mut self, name: &str, span: Range<usize>) -> Result<Arc<dyn Texture>, SceneFileError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+        let desc = match self.file.desc.textures.get(name) {
+            Some(desc) => desc,
+            None => return Err(self.file.error(span, format!("unknown texture `{}`", name))),
+        };
+        if let Some(start) = self.resolving.iter().position(|n| n == name) {
+            let cycle = self.resolving[start..].join("` -> `");
+            return Err(self.file.error(desc.span(), format!("texture reference cycle `{}` -> `{}`", cycle, name)));
+        }
+
+        self.resolving.push(name.to_string());
+        let span = desc.span();
+        let texture: Arc<dyn Texture> = match desc.get_ref() {
+            TextureDesc::Solid {color} => Arc::new(SolidTexture::new(&to_vec3(color))),
+            TextureDesc::Checker {odd, even} => {
+                let odd = self.texture_ref(odd, &span)?;
+                let even = self.texture_ref(even, &span)?;
+                Arc::new(CheckerTexture::new(odd, even))
+            },
+            TextureDesc::Image {path} => {
+                let resolved = self.file.resolve_path(path);
+                let texture = ImageTexture::open(&resolved.to_string_lossy()).map_err(|e| {
+                    self.file.error(span.clone(), format!("failed to load image {}: {}", resolved.display(), e))
+                })?;
+                Arc::new(texture)
+            },
+            TextureDesc::Noise {pattern, color, scale, octaves, seed} => {
+                let kind = match pattern {
+                    NoisePattern::Noise => NoiseKind::Noise,
+                    NoisePattern::Turbulence => NoiseKind::Turbulence,
+                    NoisePattern::Marble => NoiseKind::Marble,
+                    NoisePattern::Fbm => NoiseKind::Fbm,
+                };
+                Arc::new(NoiseTexture::new(kind, &to_vec3(color), *scale, *octaves, *seed))
+            },
+        };
+        self.resolving.pop();
+
+        self.textures.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn texture_ref(&mut self, texture: &TextureRef, span: &Range<usize>) -> Result<Arc<dyn Texture>, SceneFileError> {
+        match texture {
+            TextureRef::Color(color) => Ok(Arc::new(SolidTexture::new(&to_vec3(color)))),
+            TextureRef::Name(name) => self.texture(name, span.clone()),
+        }
+    }
+
+    // The referenced texture, or default when there is none
+    fn optional_texture_ref(&mut self, texture: &Option<TextureRef>, default: &Arc<dyn Texture>, span: &Range<usize>)
+            -> Result<Arc<dyn Texture>, SceneFileError> {
+        match texture {
+            Some(texture) => self.texture_ref(texture, span),
+            None => Ok(default.clone()),
+        }
+    }
+
+    fn material(&mut self, desc: &Spanned<MaterialDesc>) -> Result<Arc<dyn Material>, SceneFileError> {
+        let span = desc.span();
+        let material: Arc<dyn Material> = match desc.get_ref() {
+            MaterialDesc::Lambertian {albedo} => Arc::new(Lambertian::new(self.texture_ref(albedo, &span)?)),
+            MaterialDesc::Metal {albedo, fuzz} => Arc::new(Metal::new(self.texture_ref(albedo, &span)?, *fuzz)),
+            MaterialDesc::Conductor {metal, eta, k, roughness} => match (metal, eta, k) {
+                (Some(metal), None, None) => Arc::new(Conductor::preset(*metal, *roughness)),
+                (None, Some(eta), Some(k)) => Arc::new(Conductor::new(&to_vec3(eta), &to_vec3(k), *roughness)),
+                _ => return Err(self.file.error(span, String::from("conductor needs either a metal or both eta and k"))),
+            },
+            MaterialDesc::Dielectric {ior, dispersion, roughness, absorption, tint, tint_distance} => {
+                let dispersion = dispersion.as_ref().map(DispersionDesc::build);
+                let ior = match (ior, dispersion) {
+                    (Some(ior), None) => *ior,
+                    (None, Some(dispersion)) => dispersion.index(D_LINE_WAVELENGTH),
+                    _ => return Err(self.file.error(span, String::from("dielectric needs either ior or dispersion"))),
+                };
+                let dielectric = match roughness {
+                    Some(roughness) => Dielectric::with_roughness(ior, self.texture_ref(roughness, &span)?),
+                    None => Dielectric::new(ior),
+                };
+                let dielectric = match dispersion {
+                    Some(dispersion) => dielectric.dispersing(dispersion),
+                    None => dielectric,
+                };
+                match (absorption, tint) {
+                    (None, None) => Arc::new(dielectric),
+                    (Some(absorption), None) => Arc::new(dielectric.absorbing(&to_vec3(absorption))),
+                    (None, Some(tint)) => {
+                        if tint.iter().any(|&c| c <= 0.0 || c > 1.0) || *tint_distance <= 0.0 {
+                            return Err(self.file.error(span, String::from("tint must be in (0, 1] over a positive tint_distance")));
+                        }
+                        Arc::new(dielectric.absorbing(&Dielectric::tint_absorption(&to_vec3(tint), *tint_distance)))
+                    },
+                    (Some(_), Some(_)) => {
+                        return Err(self.file.error(span, String::from("dielectric takes either absorption or tint")));
+                    },
+                }
+            },
+            MaterialDesc::Principled {
+                base_color, metallic, roughness, specular, specular_tint, sheen, clearcoat, transmission, ior,
+            } => {
+                // Numbers and solid textures are known here, other textures only at the hit point
+                let constant_ior = match ior {
+                    Some(TextureRef::Color(color)) => Some(to_vec3(color).luminance()),
+                    Some(TextureRef::Name(name)) => match self.file.desc.textures.get(name).map(Spanned::get_ref) {
+                        Some(TextureDesc::Solid {color}) => Some(to_vec3(color).luminance()),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                if matches!(constant_ior, Some(ior) if !positive(ior)) {
+                    return Err(self.file.error(span, String::from("principled ior must be positive")));
+                }
+                let defaults = PrincipledParameters::default();
+                Arc::new(Principled::new(PrincipledParameters {
+                    base_color: self.optional_texture_ref(base_color, &defaults.base_color, &span)?,
+                    metallic: self.optional_texture_ref(metallic, &defaults.metallic, &span)?,
+                    roughness: self.optional_texture_ref(roughness, &defaults.roughness, &span)?,
+                    specular: self.optional_texture_ref(specular, &defaults.specular, &span)?,
+                    specular_tint: self.optional_texture_ref(specular_tint, &defaults.specular_tint, &span)?,
+                    sheen: self.optional_texture_ref(sheen, &defaults.sheen, &span)?,
+                    clearcoat: self.optional_texture_ref(clearcoat, &defaults.clearcoat, &span)?,
+                    transmission: self.optional_texture_ref(transmission, &defaults.transmission, &span)?,
+                    ior: self.optional_texture_ref(ior, &defaults.ior, &span)?,
+                }))
+            },
+            MaterialDesc::DiffuseLight {emit} => Arc::new(DiffuseLight::new(self.texture_ref(emit, &span)?)),
+            MaterialDesc::Isotropic {albedo} => Arc::new(Isotropic::new(self.texture_ref(albedo, &span)?)),
+        };
+        Ok(material)
+    }
+
+    // Returns the object, or a mesh's parts. boundary_material stands in for the material of
+    // a medium's boundary, which is never shaded.
+    fn object(&mut self, desc: &ObjectDesc, span: &Range<usize>, boundary_material: Option<&Arc<dyn Material>>)
+            -> Result<BuiltObjects, SceneFileError> {
+        let (mut objects, transform): (BuiltObjects, _) = match desc {
+            ObjectDesc::Sphere {center, radius, material, transform} => {
+                if !positive(*radius) {
+                    return Err(self.file.error(span.clone(), String::from("sphere radius must be positive")));
+                }
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                (vec![(Arc::new(Sphere::new(&to_vec3(center), *radius, material)), emissive)], transform)
+            },
+            ObjectDesc::MovingSphere {center0, center1, duration, radius, material, transform} => {
+                if !positive(*radius) {
+                    return Err(self.file.error(span.clone(), String::from("sphere radius must be positive")));
+                }
+                if !positive(*duration) {
+                    return Err(self.file.error(span.clone(), String::from("moving sphere duration must be positive")));
+                }
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                let sphere = AnimatedSphere::new(&to_vec3(center0), &to_vec3(center1), *duration, *radius, material);
+                (vec![(Arc::new(sphere), emissive)], transform)
+            },
+            ObjectDesc::Rect {plane, min, max, offset, material, transform} => {
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                let rect = match plane {
+                    RectPlane::Xy => AARect::new_xy(min[0], max[0], min[1], max[1], *offset, material),
+                    RectPlane::Xz => AARect::new_xz(min[0], max[0], min[1], max[1], *offset, material),
+                    RectPlane::Yz => AARect::new_yz(min[0], max[0], min[1], max[1], *offset, material),
+                };
+                (vec![(Arc::new(rect), emissive)], transform)
+            },
+            ObjectDesc::Plane {point, normal, material, transform} => {
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                if to_vec3(normal).length_squared() == 0.0 {
+                    return Err(self.file.error(span.clone(), String::from("plane normal is zero")));
+                }
+                (vec![(Arc::new(Plane::new(&to_vec3(point), &to_vec3(normal), material)), emissive)], transform)
+            },
+            ObjectDesc::Box {min, max, material, transform} => {
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                (vec![(Arc::new(AABox::new(&to_vec3(min), &to_vec3(max), material)), emissive)], transform)
+            },
+            ObjectDesc::Triangle {vertices, material, transform} => {
+                let (material, emissive) = self.object_material(material, span, boundary_material)?;
+                let [v0, v1, v2] = vertices;
+                let triangle = Triangle::new(&to_vec3(v0), &to_vec3(v1), &to_vec3(v2), material);
+                (vec![(Arc::new(triangle), emissive)], transform)
+            },
+            ObjectDesc::Mesh {path, material, transform} => {
+                // Faces without a usemtl statement get the object's material
+                let (material, emissive) = match material {
+                    Some(_) => self.object_material(material, span, boundary_material)?,
+                    None => (default_mesh_material(), false),
+                };
+                let meshes = load_obj(self.file.resolve_path(path), material, emissive)
+                    .map_err(|e| self.file.error(span.clone(), format!("failed to load mesh {}", e)))?;
+                let meshes = meshes.into_iter()
+                    .filter(|(mesh, _)| !mesh.is_empty())
+                    .map(|(mesh, emissive)| (Arc::new(mesh) as Arc<dyn Hittable>, emissive))
+                    .collect();
+                (meshes, transform)
+            },
+            ObjectDesc::Medium {boundary, density, albedo, transform} => {
+                if !positive(*density) {
+                    return Err(self.file.error(span.clone(), String::from("medium density must be positive")));
+                }
+                let phase_function: Arc<dyn Material> = Arc::new(Isotropic::new(self.texture_ref(albedo, span)?));
+                let mut medium = BuiltObjects::new();
+                for (boundary, _) in self.object(boundary, span, Some(&phase_function))? {
+                    medium.push((Arc::new(ConstantMedium::new(boundary, phase_function.clone(), *density)), false));
+                }
+                (medium, transform)
+            },
+        };
+
+        if let Some(transform) = transform {
+            let matrix = transform.matrix();
+            for (object, _) in objects.iter_mut() {
+                let transformed = Transform::new(object.clone(), matrix)
+                    .ok_or_else(|| self.file.error(span.clone(), String::from("transform is not invertible")))?;
+                *object = Arc::new(transformed);
+            }
+        }
+        Ok(objects)
+    }
+
+    fn object_material(&self, name: &Option<String>, span: &Range<usize>, boundary_material: Option<&Arc<dyn Material>>)
+            -> Result<(Arc<dyn Material>, bool), SceneFileError> {
+        if let Some(material) = boundary_material {
+            return Ok((material.clone(), false));
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return Err(self.file.error(span.clone(), String::from("object has no material"))),
+        };
+        match self.materials.get(name) {
+            Some(material) => Ok((material.clone(), self.is_emissive(name))),
+            None => Err(self.file.error(span.clone(), format!("unknown material `{}`", name))),
+        }
+    }
+
+    fn is_emissive(&self, name: &str) -> bool {
+        matches!(self.file.desc.materials.get(name).map(|m| m.get_ref()), Some(MaterialDesc::DiffuseLight {..}))
+    }
+
+    fn background(&mut self, desc: &Spanned<BackgroundDesc>) -> Result<Arc<dyn Environment>, SceneFileError> {
+        let environment: Arc<dyn Environment> = match desc.get_ref() {
+            BackgroundDesc::Solid {color, intensity} => Arc::new(SolidBackground::new(&to_vec3(color), *intensity)),
+            BackgroundDesc::Gradient {horizon, zenith, rotation, intensity} => {
+                Arc::new(GradientSky::new(&to_vec3(horizon), &to_vec3(zenith), &to_vec3(rotation), *intensity))
+            },
+            BackgroundDesc::Image {path, rotation, intensity} => {
+                let resolved = self.file.resolve_path(path);
+                let environment = ImageEnvironment::new(&resolved, &to_vec3(rotation), *intensity).map_err(|e| {
+                    self.file.error(desc.span(), format!("failed to load environment map {}: {}", resolved.display(), e))
+                })?;
+                Arc::new(environment)
+            },
+        };
+        Ok(environment)
+    }
+}
+
+// Finite and above zero, TOML numbers may also be nan or inf
+fn positive(v: f64) -> bool {
+    v > 0.0 && v.is_finite()
+}
+
+fn to_vec3(v: &[f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn default_mesh_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.73, 0.73, 0.73)))))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Spanned<CameraDesc>,
+    #[serde(default)]
+    render: RenderOptions,
+    background: Option<Spanned<BackgroundDesc>>,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    // Vertical field of view in degrees
+    fov: f64,
+    #[serde(default)]
+    aperture: f64,
+    // Defaults to the distance between look_from and look_at
+    focus_distance: Option<f64>,
+    #[serde(default = "default_one")]
+    shutter: f64,
+}
+
+impl CameraDesc {
+    // span locates the camera table, for settings that would make a degenerate camera
+    fn build(&self, file: &SceneFile, span: Range<usize>, aspect_ratio: f64) -> Result<Camera, SceneFileError> {
+        let look_from: Point3 = to_vec3(&self.look_from);
+        let look_at: Point3 = to_vec3(&self.look_at);
+        let view = look_from - look_at;
+        let up = to_vec3(&self.up);
+        let focus_distance = self.focus_distance.unwrap_or_else(|| view.length());
+        let message = if !positive(self.fov) || self.fov >= 180.0 {
+            Some("fov must be between 0 and 180 degrees")
+        } else if view.near_zero() {
+            Some("look_from and look_at must be different points")
+        } else if up.cross(view).near_zero() {
+            Some("up must not be parallel to the view direction")
+        } else if !positive(focus_distance) {
+            Some("focus_distance must be positive")
+        } else if self.aperture != 0.0 && !positive(self.aperture) {
+            Some("aperture must not be negative")
+        } else if self.shutter != 0.0 && !positive(self.shutter) {
+            Some("shutter must not be negative")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            return Err(file.error(span, String::from(message)));
+        }
+
+        Ok(Camera::new(&look_from, &look_at, &up,
+                       self.fov, aspect_ratio, self.aperture, focus_distance, self.shutter))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Solid {
+        color: [f64; 3],
+        #[serde(default = "default_one")]
+        intensity: f64,
+    },
+    Gradient {
+        #[serde(default = "default_horizon")]
+        horizon: [f64; 3],
+        #[serde(default = "default_zenith")]
+        zenith: [f64; 3],
+        // Degrees around the x, y and z axes
+        #[serde(default)]
+        rotation: [f64; 3],
+        #[serde(default = "default_one")]
+        intensity: f64,
+    },
+    Image {
+        path: String,
+        #[serde(default)]
+        rotation: [f64; 3],
+        #[serde(default = "default_one")]
+        intensity: f64,
+    },
+}
+
+// Either an inline color, a number standing for a gray color or the name of a texture in the
+// textures table
+enum TextureRef {
+    Color([f64; 3]),
+    Name(String),
+}
+
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextureRefVisitor;
+
+        impl<'de> Visitor<'de> for TextureRefVisitor {
+            type Value = TextureRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color [r, g, b], a number or the name of a texture")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<TextureRef, E> {
+                Ok(TextureRef::Color([value; 3]))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<TextureRef, E> {
+                self.visit_f64(value as f64)
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureRef, E> {
+                Ok(TextureRef::Name(name.to_string()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<TextureRef, A::Error> {
+                let color = <[f64; 3]>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                Ok(TextureRef::Color(color))
+            }
+        }
+
+        deserializer.deserialize_any(TextureRefVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        odd: TextureRef,
+        even: TextureRef,
+    },
+    Image {
+        path: String,
+    },
+    Noise {
+        #[serde(default = "default_noise_pattern")]
+        pattern: NoisePattern,
+        #[serde(default = "default_white")]
+        color: [f64; 3],
+        #[serde(default = "default_one")]
+        scale: f64,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NoisePattern {
+    Noise,
+    Turbulence,
+    Marble,
+    Fbm,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: TextureRef,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    // A rough metal, one of the preset metals or a complex index of refraction eta + ik
+    Conductor {
+        metal: Option<MetalPreset>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    // Glass with either a fixed ior or one that disperses light. Rough glass wherever the luminance
+    // of roughness is above 0. Colored glass either gives its absorption coefficient per unit of
+    // distance, or the tint white light takes on after travelling tint_distance inside it.
+    Dielectric {
+        ior: Option<f64>,
+        dispersion: Option<DispersionDesc>,
+        roughness: Option<TextureRef>,
+        absorption: Option<[f64; 3]>,
+        tint: Option<[f64; 3]>,
+        #[serde(default = "default_one")]
+        tint_distance: f64,
+    },
+    // Disney's principled BSDF, every parameter a texture or a number and those left out at their
+    // defaults: base_color 0.8, roughness 0.5, specular 0.5, ior 1.5 and the rest 0
+    Principled {
+        base_color: Option<TextureRef>,
+        metallic: Option<TextureRef>,
+        roughness: Option<TextureRef>,
+        specular: Option<TextureRef>,
+        specular_tint: Option<TextureRef>,
+        sheen: Option<TextureRef>,
+        clearcoat: Option<TextureRef>,
+        transmission: Option<TextureRef>,
+        ior: Option<TextureRef>,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DispersionDesc {
+    Bk7,
+    Diamond,
+    // n = a + b / λ², with λ in micrometers
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i), with λ in micrometers
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl DispersionDesc {
+    fn build(&self) -> Dispersion {
+        match self {
+            DispersionDesc::Bk7 => Dispersion::bk7(),
+            DispersionDesc::Diamond => Dispersion::diamond(),
+            DispersionDesc::Cauchy {a, b} => Dispersion::Cauchy {a: *a, b: *b},
+            DispersionDesc::Sellmeier {b, c} => Dispersion::Sellmeier {b: *b, c: *c},
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default = "default_one")]
+        duration: f64,
+        radius: f64,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    // An axis aligned rectangle in the given plane, at offset along the remaining axis
+    Rect {
+        plane: RectPlane,
+        min: [f64; 2],
+        max: [f64; 2],
+        offset: f64,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    // An infinite plane, it is never sampled as a light
+    Plane {
+        point: [f64; 3],
+        normal: [f64; 3],
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    // A Wavefront OBJ file, its MTL materials are used where it names them
+    Mesh {
+        path: String,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    Medium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: TextureRef,
+        transform: Option<TransformDesc>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RectPlane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+// Scales, then rotates around x, y and z in that order, then translates
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default = "default_scale")]
+    scale: [f64; 3],
+    // Degrees around the x, y and z axes
+    #[serde(default)]
+    rotate: [f64; 3],
+    #[serde(default)]
+    translate: [f64; 3],
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Matrix4 {
+        Matrix4::translation(&to_vec3(&self.translate))
+            * Matrix4::rotation_z(self.rotate[2])
+            * Matrix4::rotation_y(self.rotate[1])
+            * Matrix4::rotation_x(self.rotate[0])
+            * Matrix4::scaling(&to_vec3(&self.scale))
+    }
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_octaves() -> usize {
+    7
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_scale() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_horizon() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_zenith() -> [f64; 3] {
+    [0.5, 0.7, 1.0]
+}
+
+fn default_noise_pattern() -> NoisePattern {
+    NoisePattern::Marble
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "[camera]\nlook_from = [0, 0, 5]\nlook_at = [0, 0, 0]\nfov = 40\n";
+    // Declares the material gray on lines 6 and 7, after CAMERA
+    const GRAY: &str = "\n[materials]\ngray = { type = \"lambertian\", albedo = 0.5 }\n";
+
+    // The error parsing and building source is at the given line and column and its message
+    // contains expected
+    fn assert_error_in(source: &str, line: usize, column: usize, expected: &str) {
+        match SceneFile::parse("test.toml", source.to_string()).and_then(|file| file.build(1.0)) {
+            Err(SceneFileError::Parse {line: actual_line, column: actual_column, message, ..}) => {
+                assert_eq!((actual_line, actual_column), (line, column), "{}", message);
+                assert!(message.contains(expected), "{:?} doesn't mention {:?}", message, expected);
+            },
+            Err(e) => panic!("expected a located error, got {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    // The same, for CAMERA followed by rest
+    fn assert_error(rest: &str, line: usize, column: usize, expected: &str) {
+        assert_error_in(&format!("{}{}", CAMERA, rest), line, column, expected);
+    }
+
+    #[test]
+    fn valid_file_builds() {
+        let source = format!("{}{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"gray\"\n",
+                             CAMERA, GRAY);
+        SceneFile::parse("test.toml", source).unwrap().build(1.0).unwrap();
+    }
+
+    #[test]
+    fn unknown_field_is_located_at_its_table() {
+        assert_error("\n[materials]\nred = { type = \"lambertian\", albedo = [1, 0, 0], fuzz = 0.1 }\n",
+                     7, 7, "unknown field `fuzz`");
+    }
+
+    #[test]
+    fn unknown_texture_is_located_at_the_referencing_material() {
+        assert_error("\n[materials]\nred = { type = \"lambertian\", albedo = \"missing\" }\n",
+                     7, 7, "unknown texture `missing`");
+    }
+
+    #[test]
+    fn unknown_material_is_located_at_the_object() {
+        assert_error("\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"missing\"\n",
+                     6, 1, "unknown material `missing`");
+    }
+
+    #[test]
+    fn texture_cycle_is_located_at_the_texture_it_returns_to() {
+        assert_error("\n[textures]\na = { type = \"checker\", odd = \"b\", even = 0 }\nb = { type = \"checker\", odd = 1, even = \"a\" }\n",
+                     7, 5, "texture reference cycle `a` -> `b` -> `a`");
+    }
+
+    #[test]
+    fn zero_plane_normal_is_located_at_the_plane() {
+        let rest = format!("{}\n[[objects]]\ntype = \"plane\"\npoint = [0, 0, 0]\nnormal = [0, 0, 0]\nmaterial = \"gray\"\n", GRAY);
+        assert_error(&rest, 9, 1, "plane normal is zero");
+    }
+
+    #[test]
+    fn singular_transform_is_located_at_the_object() {
+        let rest = format!("{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"gray\"\n\
+                            transform = {{ scale = [1, 0, 1] }}\n", GRAY);
+        assert_error(&rest, 9, 1, "transform is not invertible");
+    }
+
+    #[test]
+    fn zero_sphere_radius_is_located_at_the_sphere() {
+        let rest = format!("{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 0\nmaterial = \"gray\"\n", GRAY);
+        assert_error(&rest, 9, 1, "sphere radius must be positive");
+    }
+
+    #[test]
+    fn non_positive_principled_ior_is_located_at_the_material() {
+        assert_error("\n[materials]\nglass = { type = \"principled\", transmission = 1, ior = 0 }\n",
+                     7, 9, "principled ior must be positive");
+        assert_error("\n[textures]\nindex = { type = \"solid\", color = [-1.5, -1.5, -1.5] }\n\
+                      [materials]\nglass = { type = \"principled\", ior = \"index\" }\n",
+                     9, 9, "principled ior must be positive");
+    }
+
+    #[test]
+    fn negative_shutter_is_located_at_the_camera() {
+        assert_error("shutter = -1\n", 1, 1, "shutter must not be negative");
+    }
+
+    #[test]
+    fn camera_looking_at_itself_is_located_at_the_camera() {
+        assert_error_in("[camera]\nlook_from = [1, 2, 3]\nlook_at = [1, 2, 3]\nfov = 40\n", 1, 1,
+                        "look_from and look_at must be different points");
+    }
+}
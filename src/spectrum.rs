@@ -0,0 +1,45 @@
+use super::vec3::Color;
+
+// Visible wavelengths in nanometers that paths through dispersive materials are traced at
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 780.0;
+
+// Mean of the clipped linear sRGB response over the visible range, dividing by it makes the
+// wavelength weights average to white
+const RESPONSE_MEAN: [f64; 3] = [0.4404433055, 0.2884639246, 0.2732961385];
+
+// Maps u in [0, 1) uniformly to a visible wavelength
+pub fn sample_wavelength(u: f64) -> f64 {
+    MIN_WAVELENGTH + (MAX_WAVELENGTH - MIN_WAVELENGTH) * u
+}
+
+// Linear RGB weight of a path traced at a single wavelength, sampled uniformly over the visible
+// range. Averaged over wavelengths it is white, so an RGB path that continues at one wavelength
+// and is multiplied by its weight converges to the same color where nothing disperses.
+pub fn wavelength_weight(wavelength: f64) -> Color {
+    let (x, y, z) = cie_xyz(wavelength);
+    let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+    let g = -0.9692660 * x + 1.8760108 * y + 0.0415560 * z;
+    let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+
+    // Spectral colors are outside the sRGB gamut, clip instead of carrying negative light
+    Color::new(
+        r.max(0.0) / RESPONSE_MEAN[0],
+        g.max(0.0) / RESPONSE_MEAN[1],
+        b.max(0.0) / RESPONSE_MEAN[2],
+    )
+}
+
+// CIE 1931 color matching functions, the multi lobe fit of Wyman, Sloan and Shirley,
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions", 2013
+fn cie_xyz(wavelength: f64) -> (f64, f64, f64) {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean {below} else {above};
+        (-0.5 * t * t).exp()
+    };
+
+    let x = 1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8);
+    (x, y, z)
+}
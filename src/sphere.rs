@@ -1,20 +1,22 @@
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 
 use super::vec3::{Point3, Vec3};
 use super::ray::Ray;
 use super::hittable::{Hittable, HitRecord};
 use super::material::Material;
 use super::bbox::AABB;
+use super::onb::Onb;
 
 pub struct Sphere {
     center: Point3,
     radius: f64,
-    material: Rc<dyn Material>,
+    material: Arc<dyn Material>,
 }
 
 #[allow(dead_code)]
 impl Sphere {
-    pub fn new(center: &Point3, radius: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new(center: &Point3, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             center: *center, 
             radius,
@@ -31,10 +33,8 @@ impl Sphere {
 
         (phi * 0.5 * FRAC_1_PI, theta * FRAC_1_PI)
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oc = ray.origin() - self.center;
         let a = ray.dir().length_squared();
         let half_b = oc.dot(ray.dir());
@@ -58,11 +58,56 @@ impl Hittable for Sphere {
         let (u, v) = self.uv(&n);
         Some(HitRecord::new(ray, root, &p, &n, self.material.clone(), u, v))
     }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self) -> Option<AABB> {
         let r = Vec3::new(self.radius, self.radius, self.radius);
         Some(AABB::new(&(self.center - r), &(self.center + r)))
     }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        if self.intersect(&Ray::new(origin, dir, 0.0), 0.0001, f64::MAX).is_none() {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // Inside the sphere every direction hits it, so sample the whole sphere of directions
+            return 0.25 * std::f64::consts::FRAC_1_PI;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return random_in_cone(-1.0, rng);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        Onb::new(&direction).local(&random_in_cone(cos_theta_max, rng))
+    }
+}
+
+// Uniformly samples a direction within the cone around +z whose half angle has cosine cos_theta_max
+fn random_in_cone(cos_theta_max: f64, rng: &mut dyn RngCore) -> Vec3 {
+    let r1: f64 = rng.gen();
+    let r2: f64 = rng.gen();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
 }
 
 pub struct AnimatedSphere {
@@ -70,12 +115,12 @@ pub struct AnimatedSphere {
     center1: Point3,
     duration: f64,
     radius: f64,
-    material: Rc<dyn Material>, 
+    material: Arc<dyn Material>, 
 }
 
 #[allow(dead_code)]
 impl AnimatedSphere {
-    pub fn new(center0: &Point3, center1: &Point3, duration: f64, radius: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new(center0: &Point3, center1: &Point3, duration: f64, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             center0: *center0,
             center1: *center1,
@@ -103,7 +148,7 @@ impl AnimatedSphere {
 }
 
 impl Hittable for AnimatedSphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
         let cur_center = self.center(ray.t());
 
         let oc = ray.origin() - cur_center;
@@ -1,8 +1,9 @@
 use image::{self, io::Reader as ImageReader, RgbImage, DynamicImage, ImageResult};
-use std::rc::Rc;
+use std::sync::Arc;
 use super::vec3::{Point3, Color};
+use super::perlin::Perlin;
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
 }
 
@@ -24,12 +25,12 @@ impl Texture for SolidTexture {
 }
 
 pub struct CheckerTexture {
-    odd: Rc<dyn Texture>,
-    even: Rc<dyn Texture>, 
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>, 
 }
 
 impl CheckerTexture {
-    pub fn new(odd: Rc<dyn Texture>, even: Rc<dyn Texture>) -> Self {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>) -> Self {
         Self {odd, even}
     } 
 }
@@ -51,16 +52,16 @@ pub struct ImageTexture {
 
 impl ImageTexture {
     pub fn new(path: &str) -> Self {
-        let img = ImageTexture::load(path);
-        if img.is_err() {
-            return Self {
-                image: None,
-            };
-        }
+        ImageTexture::open(path).unwrap_or(Self {
+            image: None,
+        })
+    }
 
-        Self {
-            image: Some(img.unwrap().to_rgb8())
-        }
+    // Like new, but reports images that fail to load instead of rendering them as solid cyan
+    pub fn open(path: &str) -> ImageResult<Self> {
+        Ok(Self {
+            image: Some(ImageTexture::load(path)?.to_rgb8())
+        })
     }
 
     fn load(path: &str) -> ImageResult<DynamicImage> {
@@ -92,6 +93,53 @@ impl Texture for ImageTexture {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    // Smooth noise remapped to [0, 1]
+    Noise,
+    // Sum of absolute noise octaves
+    Turbulence,
+    // Sine bands along z, phase shifted by turbulence
+    Marble,
+    // Fractional Brownian motion remapped to [0, 1]
+    Fbm,
+}
+
+pub struct NoiseTexture {
+    perlin: Perlin,
+    kind: NoiseKind,
+    color: Color,
+    scale: f64,
+    octaves: usize,
+}
+
+#[allow(dead_code)]
+impl NoiseTexture {
+    // scale is the noise frequency in world space, octaves is ignored by NoiseKind::Noise
+    pub fn new(kind: NoiseKind, color: &Color, scale: f64, octaves: usize, seed: u64) -> Self {
+        Self {
+            perlin: Perlin::new(seed),
+            kind,
+            color: *color,
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let scaled = *p * self.scale;
+        let intensity = match self.kind {
+            NoiseKind::Noise => 0.5 * (1.0 + self.perlin.noise(&scaled)),
+            NoiseKind::Turbulence => self.perlin.turbulence(&scaled, self.octaves),
+            NoiseKind::Marble => 0.5 * (1.0 + (scaled.z() + 10.0 * self.perlin.turbulence(p, self.octaves)).sin()),
+            NoiseKind::Fbm => 0.5 * (1.0 + self.perlin.fbm(&scaled, self.octaves)),
+        };
+        self.color * clamp(intensity, 0.0, 1.0)
+    }
+}
+
 fn clamp(v: f64, min: f64, max: f64) -> f64 {
     if v < min {
         min
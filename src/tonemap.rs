@@ -0,0 +1,219 @@
+use std::str::FromStr;
+
+use super::vec3::{Vec3, Color};
+use super::framebuffer::Framebuffer;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ToneMapOperator {
+    // Clip every channel to [0, 1]
+    #[default]
+    Clamp,
+    // c / (1 + c)
+    Reinhard,
+    // Reinhard with a white point that maps to 1.0 instead of infinity
+    ReinhardExtended {
+        white: f64,
+    },
+    // John Hable's Uncharted 2 filmic curve
+    Hable,
+    // Stephen Hill's fit of the ACES reference rendering and output transforms
+    Aces,
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "reinhard-extended" => Ok(ToneMapOperator::ReinhardExtended {white: 4.0}),
+            "hable" | "filmic" => Ok(ToneMapOperator::Hable),
+            "aces" => Ok(ToneMapOperator::Aces),
+            _ => Err(format!("Unknown tone mapping operator {}", s)),
+        }
+    }
+}
+
+impl ToneMapOperator {
+    // Maps a linear scene referred color to a linear display referred color in [0, 1]
+    pub fn map(&self, color: &Color) -> Color {
+        let color = match self {
+            ToneMapOperator::Clamp => *color,
+            ToneMapOperator::Reinhard => per_channel(color, |c| c / (1.0 + c)),
+            ToneMapOperator::ReinhardExtended {white} => {
+                let white_2 = white * white;
+                per_channel(color, |c| c * (1.0 + c / white_2) / (1.0 + c))
+            },
+            ToneMapOperator::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const WHITE: f64 = 11.2;
+                let white_scale = 1.0 / hable_partial(WHITE);
+                per_channel(color, |c| hable_partial(c * EXPOSURE_BIAS) * white_scale)
+            },
+            ToneMapOperator::Aces => aces_fitted(color),
+        };
+        per_channel(&color, |c| c.clamp(0.0, 1.0))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ToneMapper {
+    operator: ToneMapOperator,
+    // Exposure adjustment in stops, every stop doubles the brightness
+    exposure: f64,
+}
+
+#[allow(dead_code)]
+impl ToneMapper {
+    pub fn new(operator: ToneMapOperator, exposure: f64) -> Self {
+        Self {
+            operator,
+            exposure,
+        }
+    }
+
+    pub fn operator(&self) -> ToneMapOperator {
+        self.operator
+    }
+
+    pub fn exposure(&self) -> f64 {
+        self.exposure
+    }
+
+    pub fn map(&self, color: &Color) -> Color {
+        self.operator.map(&(*color * self.exposure.exp2()))
+    }
+
+    // Returns a copy of the image with every pixel exposed and tone mapped to [0, 1]
+    pub fn apply(&self, image: &Framebuffer) -> Framebuffer {
+        let mut ret = image.clone();
+        for color in ret.pixels_mut() {
+            *color = self.map(color);
+        }
+        ret
+    }
+}
+
+// The sRGB opto-electronic transfer function, from linear [0, 1] to encoded [0, 1]
+pub fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Inverse of linear_to_srgb
+pub fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn per_channel<F: Fn(f64) -> f64>(color: &Color, f: F) -> Color {
+    Color::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+fn hable_partial(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn aces_fitted(color: &Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f64; 3]; 3] = [
+        [ 1.60475, -0.53108, -0.07367],
+        [-0.10208,  1.10813, -0.00605],
+        [-0.00327, -0.07276,  1.07602],
+    ];
+
+    let color = mul_matrix(&INPUT, color);
+    // RRT and ODT fit
+    let color = per_channel(&color, |c| {
+        let a = c * (c + 0.0245786) - 0.000090537;
+        let b = c * (0.983729 * c + 0.4329510) + 0.238081;
+        a / b
+    });
+    mul_matrix(&OUTPUT, &color)
+}
+
+fn mul_matrix(m: &[[f64; 3]; 3], v: &Vec3) -> Vec3 {
+    Vec3::new(m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    fn assert_gray(color: &Color, expected: f64) {
+        for i in 0..3 {
+            assert_close(color[i], expected);
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_curve() {
+        assert_close(linear_to_srgb(0.0), 0.0);
+        // The linear segment, and where it meets the power curve
+        assert_close(linear_to_srgb(0.002), 0.02584);
+        assert_close(linear_to_srgb(0.0031308), 0.04045);
+        assert_close(linear_to_srgb(0.18), 0.461356);
+        assert_close(linear_to_srgb(0.5), 0.735357);
+        assert_close(linear_to_srgb(1.0), 1.0);
+        for &v in [0.001, 0.01, 0.18, 0.5, 0.9].iter() {
+            assert_close(srgb_to_linear(linear_to_srgb(v)), v);
+        }
+    }
+
+    #[test]
+    fn hable_maps_its_white_point_to_one() {
+        let hable = ToneMapOperator::Hable;
+        assert_gray(&hable.map(&Color::new(0.0, 0.0, 0.0)), 0.0);
+        assert_gray(&hable.map(&Color::new(0.18, 0.18, 0.18)), 0.128338);
+        assert_gray(&hable.map(&Color::new(1.0, 1.0, 1.0)), 0.492919);
+        // The white point of 11.2 applies after the exposure bias of 2
+        assert_gray(&hable.map(&Color::new(5.6, 5.6, 5.6)), 1.0);
+    }
+
+    #[test]
+    fn aces_fit() {
+        let aces = ToneMapOperator::Aces;
+        assert_gray(&aces.map(&Color::new(0.0, 0.0, 0.0)), 0.0);
+        assert_gray(&aces.map(&Color::new(0.18, 0.18, 0.18)), 0.105591);
+        assert_gray(&aces.map(&Color::new(1.0, 1.0, 1.0)), 0.619115);
+        // The input and output matrices mix the channels of saturated colors
+        let color = aces.map(&Color::new(1.0, 0.5, 0.1));
+        assert_close(color.x(), 0.638871);
+        assert_close(color.y(), 0.383867);
+        assert_close(color.z(), 0.082200);
+        assert_gray(&aces.map(&Color::new(1000.0, 1000.0, 1000.0)), 1.0);
+    }
+
+    #[test]
+    fn reinhard_extended_maps_white_to_one() {
+        let reinhard = ToneMapOperator::ReinhardExtended {white: 4.0};
+        assert_gray(&reinhard.map(&Color::new(4.0, 4.0, 4.0)), 1.0);
+        assert_gray(&reinhard.map(&Color::new(1.0, 1.0, 1.0)), 1.0625 / 2.0);
+        assert_gray(&ToneMapOperator::Reinhard.map(&Color::new(1.0, 1.0, 1.0)), 0.5);
+    }
+}
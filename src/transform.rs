@@ -0,0 +1,449 @@
+use rand::RngCore;
+use std::ops;
+use std::sync::Arc;
+
+use super::vec3::{Point3, Vec3};
+use super::ray::Ray;
+use super::hittable::{Hittable, HitRecord};
+use super::bbox::AABB;
+
+// Row major 4x4 matrix, points are column vectors with an implicit w of 1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+#[allow(dead_code)]
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self {m}
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: &Vec3) -> Self {
+        let mut ret = Self::identity();
+        for i in 0..3 {
+            ret.m[i][3] = offset[i];
+        }
+        ret
+    }
+
+    pub fn scaling(scale: &Vec3) -> Self {
+        let mut ret = Self::identity();
+        for i in 0..3 {
+            ret.m[i][i] = scale[i];
+        }
+        ret
+    }
+
+    // Counterclockwise rotation around the x axis, looking from +x towards the origin
+    pub fn rotation_x(degrees: f64) -> Self {
+        Self::rotation(0, degrees)
+    }
+
+    pub fn rotation_y(degrees: f64) -> Self {
+        Self::rotation(1, degrees)
+    }
+
+    pub fn rotation_z(degrees: f64) -> Self {
+        Self::rotation(2, degrees)
+    }
+
+    fn rotation(axis: usize, degrees: f64) -> Self {
+        let (sin_theta, cos_theta) = degrees.to_radians().sin_cos();
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut ret = Self::identity();
+        ret.m[a][a] = cos_theta;
+        ret.m[a][b] = -sin_theta;
+        ret.m[b][a] = sin_theta;
+        ret.m[b][b] = cos_theta;
+        ret
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut ret = Self::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                ret.m[i][j] = self.m[j][i];
+            }
+        }
+        ret
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+                    m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+                    m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3])
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+                m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+                m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z())
+    }
+
+    // Determinant of the upper left 3x3 part, the factor the matrix scales volumes by
+    pub fn linear_determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // Bounding box of the transformed corners of a box
+    pub fn transform_box(&self, aabb: &AABB) -> AABB {
+        let (min, max) = (aabb.min(), aabb.max());
+        let mut ret = AABB::default();
+        for i in 0..8 {
+            let corner = Point3::new(if i & 1 == 0 {min.x()} else {max.x()},
+                                    if i & 2 == 0 {min.y()} else {max.y()},
+                                    if i & 4 == 0 {min.z()} else {max.z()});
+            let p = self.transform_point(&corner);
+            ret.merge(&AABB::new(&p, &p));
+        }
+        ret
+    }
+}
+
+impl ops::Mul for Matrix4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self::Output {
+        let mut ret = [[0.0; 4]; 4];
+        for (i, row) in ret.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self::new(ret)
+    }
+}
+
+// Places an object in the world with an affine object to world matrix. Light sampling through
+// pdf_value and random samples the object in its own space, and the densities are converted to
+// world space solid angles, so scaled and sheared lights are sampled without bias.
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+    // Inverse transpose, which maps object space normals to world space
+    normal_matrix: Matrix4,
+    // |det| of the inverse's linear part, for the change of solid angle between the spaces
+    inverse_determinant: f64,
+    bounding_box: Option<AABB>,
+}
+
+#[allow(dead_code)]
+impl Transform {
+    // None if the matrix is not invertible
+    pub fn new(object: Arc<dyn Hittable>, matrix: Matrix4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        let bounding_box = object.bounding_box().map(|b| matrix.transform_box(&b));
+        Some(Self {
+            object, matrix, inverse,
+            normal_matrix: inverse.transpose(),
+            inverse_determinant: inverse.linear_determinant().abs(),
+            bounding_box,
+        })
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        // The direction isn't normalized, so t is the same in both spaces
+        let object_ray = Ray::new(&self.inverse.transform_point(&ray.origin()),
+                                &self.inverse.transform_vector(&ray.dir()),
+                                ray.t());
+        let mut rec = self.object.hit(&object_ray, t_min, t_max, rng)?;
+        rec.p = self.matrix.transform_point(&rec.p);
+        // front_face stays valid, the sign of dir . normal doesn't change under the transform
+        rec.normal = self.normal_matrix.transform_vector(&rec.normal).unit_vector();
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bounding_box
+    }
+
+    // A unit direction w maps to the object space direction A w / |A w| for the inverse's linear
+    // part A, which changes solid angles by the factor |det A| / |A w|^3
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        let object_dir = self.inverse.transform_vector(&dir.unit_vector());
+        let object_pdf = self.object.pdf_value(&self.inverse.transform_point(origin), &object_dir);
+        object_pdf * self.inverse_determinant / object_dir.length().powi(3)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.matrix.transform_vector(&self.object.random(&self.inverse.transform_point(origin), rng))
+    }
+}
+
+pub struct Translate {
+    object: Arc<dyn Hittable>,
+    offset: Vec3,
+}
+
+#[allow(dead_code)]
+impl Translate {
+    pub fn new(object: Arc<dyn Hittable>, offset: &Vec3) -> Self {
+        Self {
+            object,
+            offset: *offset,
+        }
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let moved = Ray::new(&(ray.origin() - self.offset), &ray.dir(), ray.t());
+        let mut rec = self.object.hit(&moved, t_min, t_max, rng)?;
+        rec.p += self.offset;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let aabb = self.object.bounding_box()?;
+        Some(AABB::new(&(aabb.min() + self.offset), &(aabb.max() + self.offset)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        self.object.pdf_value(&(*origin - self.offset), dir)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(&(*origin - self.offset), rng)
+    }
+}
+
+// Rotation around one of the coordinate axes, use the RotateX, RotateY and RotateZ aliases
+pub struct Rotate<const AXIS: usize> {
+    object: Arc<dyn Hittable>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bounding_box: Option<AABB>,
+}
+
+pub type RotateX = Rotate<0>;
+pub type RotateY = Rotate<1>;
+pub type RotateZ = Rotate<2>;
+
+#[allow(dead_code)]
+impl<const AXIS: usize> Rotate<AXIS> {
+    // Counterclockwise rotation in degrees, looking from the positive axis towards the origin
+    pub fn new(object: Arc<dyn Hittable>, degrees: f64) -> Self {
+        let (sin_theta, cos_theta) = degrees.to_radians().sin_cos();
+        let bounding_box = object.bounding_box()
+            .map(|b| Matrix4::rotation(AXIS, degrees).transform_box(&b));
+        Self {
+            object,
+            sin_theta, cos_theta,
+            bounding_box,
+        }
+    }
+
+    // Rotates by the angle, or by its negation to go from world to object space
+    fn rotate(&self, v: &Vec3, inverse: bool) -> Vec3 {
+        let (a, b) = ((AXIS + 1) % 3, (AXIS + 2) % 3);
+        let sin_theta = if inverse {-self.sin_theta} else {self.sin_theta};
+        let mut ret = *v;
+        ret[a] = self.cos_theta * v[a] - sin_theta * v[b];
+        ret[b] = sin_theta * v[a] + self.cos_theta * v[b];
+        ret
+    }
+}
+
+impl<const AXIS: usize> Hittable for Rotate<AXIS> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let rotated = Ray::new(&self.rotate(&ray.origin(), true), &self.rotate(&ray.dir(), true), ray.t());
+        let mut rec = self.object.hit(&rotated, t_min, t_max, rng)?;
+        rec.p = self.rotate(&rec.p, false);
+        rec.normal = self.rotate(&rec.normal, false);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bounding_box
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        self.object.pdf_value(&self.rotate(origin, true), &self.rotate(dir, true))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.rotate(&self.object.random(&self.rotate(origin, true), rng), false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::aabox::AABox;
+    use crate::material::{Material, Lambertian};
+    use crate::rect::AARect;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidTexture;
+    use crate::triangle::Triangle;
+    use crate::vec3::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.5, 0.5, 0.5)))))
+    }
+
+    // Scales unevenly, shears x along y, rotates and moves the object away from the origin
+    fn skewed() -> Matrix4 {
+        let mut shear = Matrix4::identity();
+        shear.m[0][1] = 0.7;
+        Matrix4::translation(&Vec3::new(1.0, 2.0, -6.0)) * Matrix4::rotation_y(30.0) * Matrix4::rotation_x(-20.0)
+            * shear * Matrix4::scaling(&Vec3::new(3.0, 1.0, 0.5))
+    }
+
+    fn random_unit_vector(rng: &mut StdRng) -> Vec3 {
+        loop {
+            let v = Vec3::random_in(-1.0, 1.0, rng);
+            if v.length_squared() > 1e-6 && v.length_squared() <= 1.0 {
+                return v.unit_vector();
+            }
+        }
+    }
+
+    #[test]
+    fn singular_matrix_is_rejected() {
+        let sphere = Arc::new(Sphere::new(&Point3::default(), 1.0, material()));
+        assert!(Transform::new(sphere, Matrix4::scaling(&Vec3::new(1.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    fn bounding_box_covers_the_rotated_and_scaled_corners() {
+        let unit_box = Arc::new(AABox::new(&Point3::new(0.0, 0.0, 0.0), &Point3::new(1.0, 1.0, 1.0), material()));
+        let matrix = Matrix4::translation(&Vec3::new(0.0, 5.0, 0.0)) * Matrix4::rotation_y(45.0)
+            * Matrix4::scaling(&Vec3::new(2.0, 3.0, 1.0));
+        let aabb = Transform::new(unit_box, matrix).unwrap().bounding_box().unwrap();
+
+        // Rotating the 2 by 1 footprint by 45 degrees around y turns x into (x + z) / sqrt(2)
+        // and z into (z - x) / sqrt(2)
+        let h = 0.5f64.sqrt();
+        let expected = AABB::new(&Point3::new(0.0, 5.0, -2.0 * h), &Point3::new(3.0 * h, 8.0, h));
+        assert!((aabb.min() - expected.min()).length() < 1e-12, "{:?}", aabb);
+        assert!((aabb.max() - expected.max()).length() < 1e-12, "{:?}", aabb);
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let v = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 1.0), Point3::new(0.0, 1.0, 1.0)];
+        let matrix = skewed();
+        let triangle = Transform::new(Arc::new(Triangle::new(&v[0], &v[1], &v[2], material())), matrix).unwrap();
+        let w: Vec<Point3> = v.iter().map(|p| matrix.transform_point(p)).collect();
+        let (e1, e2) = (w[1] - w[0], w[2] - w[0]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let origin = Point3::new(0.0, 20.0, 20.0);
+        let mut hits = 0;
+        for _ in 0..100 {
+            let target = w[0] + e1 * 0.3 + e2 * 0.3 + Vec3::random_in(-0.5, 0.5, &mut rng);
+            let ray = Ray::new(&origin, &(target - origin), 0.0);
+            if let Some(rec) = triangle.hit(&ray, 0.0001, f64::MAX, &mut rng) {
+                hits += 1;
+                assert!((rec.normal.length() - 1.0).abs() < 1e-9);
+                assert!(rec.normal.dot(e1).abs() < 1e-9 * e1.length());
+                assert!(rec.normal.dot(e2).abs() < 1e-9 * e2.length());
+                // The hit point lies in the world space triangle's plane
+                assert!(rec.normal.dot(rec.p - w[0]).abs() < 1e-9);
+            }
+        }
+        assert!(hits > 50, "{} of 100 rays hit", hits);
+    }
+
+    // Sampling a transformed light and weighting every sample by 1 / pdf_value estimates the solid
+    // angle it covers, which counting uniformly distributed directions that hit it also estimates
+    #[test]
+    fn pdf_matches_sampling_of_skewed_lights() {
+        let lights: [(&str, Arc<dyn Hittable>); 2] = [
+            ("sphere", Arc::new(Sphere::new(&Point3::default(), 1.0, material()))),
+            ("rect", Arc::new(AARect::new_xz(-1.0, 1.0, -1.0, 1.0, 0.0, material()))),
+        ];
+        let mut rng = StdRng::seed_from_u64(2);
+        let origin = Point3::new(1.0, 4.5, -5.0);
+        const SAMPLES: usize = 200_000;
+
+        for (name, object) in lights.iter() {
+            let light = Transform::new(object.clone(), skewed()).unwrap();
+
+            let mut sampled_solid_angle = 0.0;
+            for _ in 0..SAMPLES {
+                let dir = light.random(&origin, &mut rng);
+                assert!(light.hit(&Ray::new(&origin, &dir, 0.0), 0.0001, f64::MAX, &mut rng).is_some(),
+                        "{} sampled a direction that misses it", name);
+                sampled_solid_angle += 1.0 / light.pdf_value(&origin, &dir);
+            }
+            sampled_solid_angle /= SAMPLES as f64;
+
+            let mut hits = 0;
+            let mut pdf_integral = 0.0;
+            for _ in 0..SAMPLES {
+                let dir = random_unit_vector(&mut rng);
+                if light.hit(&Ray::new(&origin, &dir, 0.0), 0.0001, f64::MAX, &mut rng).is_some() {
+                    hits += 1;
+                }
+                pdf_integral += light.pdf_value(&origin, &dir);
+            }
+            let four_pi = 4.0 * std::f64::consts::PI;
+            let counted_solid_angle = four_pi * hits as f64 / SAMPLES as f64;
+            pdf_integral *= four_pi / SAMPLES as f64;
+
+            assert!((sampled_solid_angle / counted_solid_angle - 1.0).abs() < 0.03,
+                    "{}: sampled {} counted {}", name, sampled_solid_angle, counted_solid_angle);
+            assert!((pdf_integral - 1.0).abs() < 0.03, "{}: pdf integrates to {}", name, pdf_integral);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+
+use super::vec3::{Point3, Vec3};
+use super::ray::Ray;
+use super::hittable::{Hittable, HitRecord};
+use super::bvh::BVH;
+use super::material::Material;
+use super::bbox::AABB;
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    // Per vertex shading normals, the geometric normal is used when missing
+    normals: Option<[Vec3; 3]>,
+    uvs: [(f64, f64); 3],
+    material: Arc<dyn Material>,
+}
+
+#[allow(dead_code)]
+impl Triangle {
+    // Flat shaded triangle, uv is (0, 0), (1, 0) and (0, 1) at the three vertices
+    pub fn new(v0: &Point3, v1: &Point3, v2: &Point3, material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices: [*v0, *v1, *v2],
+            normals: None,
+            uvs: DEFAULT_UVS,
+            material,
+        }
+    }
+
+    pub fn with_attributes(vertices: [Point3; 3], normals: Option<[Vec3; 3]>, uvs: Option<[(f64, f64); 3]>,
+                            material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            normals,
+            uvs: uvs.unwrap_or(DEFAULT_UVS),
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        hit_triangle(ray, t_min, t_max, &self.vertices, self.normals.as_ref(), &self.uvs, &self.material)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        triangle_pdf_value(&self.vertices, &self.material, origin, dir)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        random_on_triangle(&self.vertices, rng) - *origin
+    }
+}
+
+// Vertex buffers shared by every triangle of a mesh
+struct MeshData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+}
+
+impl MeshData {
+    fn vertices(&self, index: usize) -> [Point3; 3] {
+        let [i0, i1, i2] = self.indices[index];
+        [self.positions[i0], self.positions[i1], self.positions[i2]]
+    }
+}
+
+// Indexed triangle mesh, vertex attributes are shared between the triangles that reference them
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    bvh: Option<BVH>,
+    // Running total of the triangles' areas, to sample the mesh as a light uniformly by area
+    cumulative_areas: Vec<f64>,
+}
+
+#[allow(dead_code)]
+impl TriangleMesh {
+    // normals and uvs are either empty or have one entry per position
+    pub fn new(positions: Vec<Point3>, normals: Vec<Vec3>, uvs: Vec<(f64, f64)>,
+                indices: Vec<[usize; 3]>, material: Arc<dyn Material>) -> Self {
+        assert!(normals.is_empty() || normals.len() == positions.len(),
+                "mesh has {} normals for {} positions", normals.len(), positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len(),
+                "mesh has {} uvs for {} positions", uvs.len(), positions.len());
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()),
+                "mesh index out of range of {} positions", positions.len());
+
+        let data = Arc::new(MeshData {
+            positions, normals, uvs, indices, material,
+        });
+        let triangles = TriangleMesh::make_triangles(&data);
+        let bvh = if triangles.is_empty() {None} else {Some(BVH::new(triangles))};
+        let cumulative_areas = (0..data.indices.len())
+            .scan(0.0, |total, index| {
+                *total += triangle_area(&data.vertices(index));
+                Some(*total)
+            })
+            .collect();
+        Self {
+            data, bvh, cumulative_areas,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.cumulative_areas.last().copied().unwrap_or(0.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.indices.is_empty()
+    }
+
+    // The mesh's triangles as separate objects, to build a single BVH over several meshes
+    pub fn triangles(&self) -> Vec<Arc<dyn Hittable>> {
+        TriangleMesh::make_triangles(&self.data)
+    }
+
+    fn make_triangles(data: &Arc<MeshData>) -> Vec<Arc<dyn Hittable>> {
+        (0..data.indices.len())
+            .map(|index| Arc::new(MeshTriangle {mesh: data.clone(), index}) as Arc<dyn Hittable>)
+            .collect()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.bvh.as_ref()?.hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bvh.as_ref()?.bounding_box()
+    }
+
+    // random() picks a triangle by area, so every triangle along dir adds its own density weighted
+    // by its share of the area. Cost grows with the triangle count, emissive meshes are expected to
+    // be small.
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        let area = self.area();
+        match self.bounding_box() {
+            Some(bbox) if area > 0.0 && bbox.hit(&Ray::new(origin, dir, 0.0), 0.0001, f64::MAX) => {},
+            _ => return 0.0,
+        }
+
+        let weighted_sum: f64 = (0..self.len())
+            .map(|index| {
+                let vertices = self.data.vertices(index);
+                triangle_area(&vertices) * triangle_pdf_value(&vertices, &self.data.material, origin, dir)
+            })
+            .sum();
+        weighted_sum / area
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let area = self.area();
+        if area <= 0.0 {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+
+        let target = rng.gen::<f64>() * area;
+        let index = self.cumulative_areas.partition_point(|&a| a <= target).min(self.len() - 1);
+        random_on_triangle(&self.data.vertices(index), rng) - *origin
+    }
+}
+
+// A single triangle referencing its mesh's vertex buffers
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mesh = &self.mesh;
+        let [i0, i1, i2] = mesh.indices[self.index];
+        let normals = if mesh.normals.is_empty() {
+            None
+        } else {
+            Some([mesh.normals[i0], mesh.normals[i1], mesh.normals[i2]])
+        };
+        let uvs = if mesh.uvs.is_empty() {
+            DEFAULT_UVS
+        } else {
+            [mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]]
+        };
+        hit_triangle(ray, t_min, t_max, &mesh.vertices(self.index), normals.as_ref(), &uvs, &mesh.material)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.mesh.vertices(self.index)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        triangle_pdf_value(&self.mesh.vertices(self.index), &self.mesh.material, origin, dir)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        random_on_triangle(&self.mesh.vertices(self.index), rng) - *origin
+    }
+}
+
+const DEFAULT_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+
+// Möller–Trumbore ray triangle intersection
+fn hit_triangle(ray: &Ray, t_min: f64, t_max: f64, vertices: &[Point3; 3], normals: Option<&[Vec3; 3]>,
+                uvs: &[(f64, f64); 3], material: &Arc<dyn Material>) -> Option<HitRecord> {
+    let [v0, v1, v2] = *vertices;
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let pvec = ray.dir().cross(edge2);
+    let det = edge1.dot(pvec);
+    // The ray is parallel to the triangle's plane
+    if det == 0.0 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin() - v0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let b2 = ray.dir().dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+
+    let b0 = 1.0 - b1 - b2;
+    let p = ray.at(t);
+    let outward_normal = edge1.cross(edge2).unit_vector();
+    let u = uvs[0].0 * b0 + uvs[1].0 * b1 + uvs[2].0 * b2;
+    let v = uvs[0].1 * b0 + uvs[1].1 * b1 + uvs[2].1 * b2;
+    let mut rec = HitRecord::new(ray, t, &p, &outward_normal, material.clone(), u, v);
+
+    if let Some(n) = normals {
+        // Interpolated shading normal, kept on the same side as the geometric one
+        let shading_normal = n[0] * b0 + n[1] * b1 + n[2] * b2;
+        if !shading_normal.near_zero() {
+            let shading_normal = shading_normal.unit_vector();
+            rec.normal = if shading_normal.dot(rec.normal) < 0.0 {-shading_normal} else {shading_normal};
+        }
+    }
+    Some(rec)
+}
+
+fn triangle_bounding_box(vertices: &[Point3; 3]) -> AABB {
+    // Pad flat boxes like AARect does, so axis aligned triangles still have a volume
+    const PADDING: f64 = 0.0001;
+
+    let mut min = vertices[0];
+    let mut max = vertices[0];
+    for v in vertices[1..].iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(v[i]);
+            max[i] = max[i].max(v[i]);
+        }
+    }
+    for i in 0..3 {
+        if max[i] - min[i] < PADDING {
+            min[i] -= PADDING;
+            max[i] += PADDING;
+        }
+    }
+    AABB::new(&min, &max)
+}
+
+fn triangle_area(vertices: &[Point3; 3]) -> f64 {
+    0.5 * (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).length()
+}
+
+fn triangle_pdf_value(vertices: &[Point3; 3], material: &Arc<dyn Material>, origin: &Point3, dir: &Vec3) -> f64 {
+    let ray = Ray::new(origin, dir, 0.0);
+    if let Some(rec) = hit_triangle(&ray, 0.0001, f64::MAX, vertices, None, &DEFAULT_UVS, material) {
+        let geometric_normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]);
+        let area = triangle_area(vertices);
+        let distance_squared = rec.t * rec.t * dir.length_squared();
+        let cosine = (dir.dot(geometric_normal) / (dir.length() * geometric_normal.length())).abs();
+        distance_squared / (cosine * area)
+    } else {
+        0.0
+    }
+}
+
+// Uniformly samples a point on the triangle's surface
+fn random_on_triangle(vertices: &[Point3; 3], rng: &mut dyn RngCore) -> Point3 {
+    let r1: f64 = rng.gen();
+    let r2: f64 = rng.gen();
+    let sqrt_r1 = r1.sqrt();
+    let b0 = 1.0 - sqrt_r1;
+    let b1 = r2 * sqrt_r1;
+    vertices[0] * b0 + vertices[1] * b1 + vertices[2] * (1.0 - b0 - b1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::material::Lambertian;
+    use crate::texture::SolidTexture;
+    use crate::vec3::Color;
+
+    // A 2 by 1 rectangle at z = 0 from two triangles of different areas, and a lone triangle
+    fn mesh() -> TriangleMesh {
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0), Point3::new(2.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0), Point3::new(0.5, 1.0, 0.0),
+        ];
+        let indices = vec![[0, 1, 4], [0, 4, 3], [1, 2, 4]];
+        let material = Arc::new(Lambertian::new(Arc::new(SolidTexture::new(&Color::new(0.5, 0.5, 0.5)))));
+        TriangleMesh::new(positions, Vec::new(), Vec::new(), indices, material)
+    }
+
+    #[test]
+    fn mesh_area_sums_its_triangles() {
+        assert!((mesh().area() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mesh_pdf_matches_sampling_by_area() {
+        let mesh = mesh();
+        let origin = Point3::new(0.3, 0.2, 1.5);
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let dir = mesh.random(&origin, &mut rng);
+            // The sampled point is at dir, uniformly distributed over the area of the mesh
+            let cosine = dir.z().abs() / dir.length();
+            let expected = dir.length_squared() / (cosine * mesh.area());
+            let pdf = mesh.pdf_value(&origin, &dir);
+            assert!((pdf - expected).abs() < 1e-6 * expected, "pdf {} expected {}", pdf, expected);
+        }
+    }
+
+    #[test]
+    fn mesh_sampling_picks_triangles_by_area() {
+        let mesh = mesh();
+        let origin = Point3::new(1.0, 0.5, 1.0);
+        let mut rng = StdRng::seed_from_u64(7);
+        let n = 20000;
+        // The triangle [1, 2, 4] is right of the edge from (2, 0) to (0.5, 1), 0.75 of the area of 2
+        let in_right = (0..n)
+            .map(|_| mesh.random(&origin, &mut rng) + origin)
+            .filter(|p| p.x() > 2.0 - 1.5 * p.y())
+            .count();
+        let fraction = in_right as f64 / n as f64;
+        assert!((fraction - 0.375).abs() < 0.02, "fraction {}", fraction);
+    }
+}
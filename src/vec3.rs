@@ -1,5 +1,5 @@
 use std::{ops, fmt};
-use rand::{self,Rng};
+use rand::Rng;
 
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Vec3 {
@@ -17,15 +17,13 @@ impl Vec3 {
         Self {v: [x, y, z]}
     }
 
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
         Self{
             v: [rng.gen(), rng.gen(), rng.gen()]
         }
     }
 
-    pub fn random_in(min: f64, max: f64) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random_in<R: Rng + ?Sized>(min: f64, max: f64, rng: &mut R) -> Self {
         Self{
             v: [rng.gen_range(min..max), rng.gen_range(min..max), rng.gen_range(min..max)]
         }
@@ -80,6 +78,12 @@ impl Vec3 {
         const S: f64 = 1e-8;
         self.v[0].abs() < S && self.v[1].abs() < S && self.v[2].abs() < S
     }
+
+    // Relative luminance of a linear Rec. 709 color
+    #[inline]
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.v[0] + 0.7152 * self.v[1] + 0.0722 * self.v[2]
+    }
 }
 
 impl ops::Add for Vec3 {